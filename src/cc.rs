@@ -1,76 +1,84 @@
-use crate::emit::{EmitContextImpl, EmitDefault};
-use crate::parser::esper_parser;
+use crate::diagnostics::{json_object, render_message, Diagnostic, MessageFormat};
+use crate::error::CompileError;
+use crate::span::{LineIndex, Span};
 
-use std::error::Error;
-use std::fs::{self, File};
+use std::env;
 use std::io::Write;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use std::process::{Command, Stdio};
 
-fn file_prefix(path: &PathBuf) -> Option<String> {
-    path.file_stem()
-        .and_then(|stem| stem.to_str())
-        .map(|s| s.to_string())
+// esper module that the C++ source was generated from, the #line
+// directives in the C++ source refer to it through its path
+#[derive(Debug, Clone)]
+pub struct SourceFile<'a> {
+    pub path: &'a str,
+    pub source: &'a str,
+    // for modules generated from the source, lines of the module mapped to
+    // the lines of the source they were copied from
+    pub line_map: Option<&'a [Option<usize>]>,
 }
 
-pub(crate) fn compile(
-    input_path: PathBuf,
-    output_path: PathBuf,
-    clang_flags: Vec<String>,
-    use_prelude: bool,
-    should_emit: bool,
-) {
-    let source = fs::read_to_string(&input_path).expect("input file not found");
-
-    match esper_parser::program(&source) {
-        Ok(program) => {
-            // dbg!(&program);
-            let mut ctx = EmitContextImpl::new();
-            ctx.use_prelude = use_prelude; // force?
-            let mut emitter = EmitDefault { ctx };
-            // dbg!(file_prefix(&input_path));
-            let cxx_source = emitter.emit_program(&program, &file_prefix(&input_path).unwrap());
-            // println!("{}", &out);
-
-            if should_emit {
-                fs::write(&output_path, &cxx_source);
-            } else {
-                ClangCXX::compile(&cxx_source, output_path.to_str().unwrap(), clang_flags).unwrap();
-            }
-        }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CxxFlavor {
+    Clang,
+    Gcc,
+}
 
-        Err(peg_err) => {
-            let error_line = peg_err.location.line;
-            let error_offset = peg_err.location.offset;
-            let total_lines = source.lines().count();
+// the C++ compiler driving the backend, clang++ unless overridden with
+// --cxx or the CXX environment variable, the flavor selects builtins in
+// the emitter and flags that differ between clang++ and g++
+#[derive(Debug, Clone)]
+pub struct CxxCompiler {
+    pub program: String,
+    pub flavor: CxxFlavor,
+}
 
-            let display_start = if error_line > 2 { error_line - 2 } else { 1 };
+impl CxxCompiler {
+    pub fn new(program: &str) -> Self {
+        CxxCompiler {
+            program: program.into(),
+            flavor: Self::detect_flavor(program),
+        }
+    }
 
-            let display_end = if error_line + 2 <= total_lines {
-                error_line + 2
-            } else {
-                total_lines
-            };
+    pub fn resolve(cxx_opt: Option<String>) -> Self {
+        let program = cxx_opt
+            .or_else(|| env::var("CXX").ok().filter(|cxx| !cxx.trim().is_empty()))
+            .unwrap_or_else(|| "clang++".into());
 
-            let src_lines: Vec<&str> = source.lines().collect();
-            let src_slice = &src_lines[display_start - 1..display_end];
+        Self::new(&program)
+    }
 
-            let src = src_slice.join("\n");
+    // names are usually enough (clang++-16, g++-13, x86_64-linux-gnu-g++),
+    // generic ones like c++ are resolved by asking the compiler itself
+    fn detect_flavor(program: &str) -> CxxFlavor {
+        let name = Path::new(program)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(program);
 
-            // @fix expected won't appear unless the entire source is passed
-            let expected_msg = format!("expected: {}", peg_err.expected);
+        if name.contains("clang") {
+            return CxxFlavor::Clang;
+        }
 
-            let msg = chic::Error::new("parse error")
-                .error(
-                    error_line,
-                    error_offset,
-                    error_offset + 1,
-                    &src,
-                    expected_msg,
-                )
-                .to_string();
+        if name.contains("g++") || name.contains("gcc") {
+            return CxxFlavor::Gcc;
+        }
 
-            eprintln!("{}", msg);
+        match Command::new(program).arg("--version").output() {
+            Ok(output) if String::from_utf8_lossy(&output.stdout).contains("clang") => {
+                CxxFlavor::Clang
+            }
+            Ok(_) => CxxFlavor::Gcc,
+            Err(_) => CxxFlavor::Clang,
+        }
+    }
+
+    // excerpts of the generated C++ are dropped when remapping diagnostics
+    pub fn diagnostic_flags(&self) -> &'static [&'static str] {
+        match self.flavor {
+            CxxFlavor::Clang => &["-fno-caret-diagnostics"],
+            CxxFlavor::Gcc => &["-fno-diagnostics-show-caret"],
         }
     }
 }
@@ -81,11 +89,19 @@ pub struct ClangCXX;
 impl ClangCXX {
     // by default, we will avoid writing the cxx file unless an emit flag is used
     pub fn compile(
+        cxx: &CxxCompiler,
         cxx_source: &str,
         output_file: &str,
         clang_flags: Vec<String>,
-    ) -> std::io::Result<()> {
-        let mut clang = Command::new("clang++")
+        esper_source: &SourceFile,
+        message_format: MessageFormat,
+        messages: &mut Vec<String>,
+    ) -> Result<(), CompileError> {
+        let spawn_err =
+            |err| CompileError::Backend(format!("failed to run {}: {}", cxx.program, err));
+
+        let mut clang = Command::new(&cxx.program)
+            .args(cxx.diagnostic_flags())
             .arg("-x")
             .arg("c++")
             .arg("-") // read from stdin
@@ -94,16 +110,275 @@ impl ClangCXX {
             .args(clang_flags)
             .stdin(Stdio::piped())
             .stdout(Stdio::inherit()) // forward stdout
-            .stderr(Stdio::inherit()) // forward stderr
-            .spawn()?;
+            .stderr(Stdio::piped()) // remapped in render_diagnostics
+            .spawn()
+            .map_err(spawn_err)?;
 
-        // pipe the source to the clang++ process stdin
-        if let Some(ref mut stdin) = clang.stdin {
-            stdin.write_all(cxx_source.as_bytes())?;
+        // pipe the source to the clang++ process stdin, dropping the
+        // handle closes it so clang++ stops reading
+        if let Some(mut stdin) = clang.stdin.take() {
+            stdin.write_all(cxx_source.as_bytes()).map_err(spawn_err)?;
         }
 
-        clang.wait()?;
+        let output = clang.wait_with_output().map_err(spawn_err)?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        let rendered = Self::render_diagnostics(&stderr, esper_source, message_format);
+
+        if !rendered.is_empty() {
+            messages.push(rendered.trim_end_matches('\n').into());
+        }
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(CompileError::Backend(match output.status.code() {
+                Some(code) => format!("{} exited with status {}", cxx.program, code),
+                None => format!("{} was terminated by a signal", cxx.program),
+            }))
+        }
+    }
+
+    // diagnostics located in the esper source (through #line directives) are
+    // rendered against it with chic, notes are attached to the diagnostic they
+    // follow, anything else (prelude, linker, summaries) is forwarded verbatim
+    pub fn render_diagnostics(
+        stderr: &str,
+        esper_source: &SourceFile,
+        message_format: MessageFormat,
+    ) -> String {
+        let src_lines: Vec<&str> = esper_source.source.lines().collect();
+        let mut out = String::new();
+
+        for output in Self::collect_diagnostics(stderr, esper_source) {
+            match (output, message_format) {
+                (CxxOutput::Remapped(diag), MessageFormat::Human) => {
+                    out.push_str(&diag.render(&src_lines))
+                }
+
+                (CxxOutput::Remapped(diag), MessageFormat::Json) => {
+                    out.push_str(&diag.to_json(esper_source));
+                    out.push('\n');
+                }
+
+                (CxxOutput::Forwarded(line), MessageFormat::Human) => {
+                    out.push_str(&line);
+                    out.push('\n');
+                }
+
+                // anything clang++ reports outside of the esper source is
+                // kept as a note without a location
+                (CxxOutput::Forwarded(line), MessageFormat::Json) => {
+                    let message = match ClangDiagnostic::parse(&line) {
+                        Some(diag) => render_message(
+                            message_format,
+                            &diag.severity,
+                            Some(&diag.file),
+                            &diag.message,
+                        ),
+                        None => render_message(message_format, "note", None, &line),
+                    };
+
+                    out.push_str(&message);
+                    out.push('\n');
+                }
+            }
+        }
+
+        out
+    }
+
+    fn collect_diagnostics(stderr: &str, esper_source: &SourceFile) -> Vec<CxxOutput> {
+        let mut outputs = vec![];
+        let mut current: Option<ClangDiagnostic> = None;
+        let mut forwarding = true;
+
+        for line in stderr.lines() {
+            match ClangDiagnostic::parse(line) {
+                Some(mut diag) if diag.file == esper_source.path => {
+                    // lines generated around the source have no location
+                    if let Some(line_map) = esper_source.line_map {
+                        diag.line = line_map
+                            .get(diag.line.wrapping_sub(1))
+                            .copied()
+                            .flatten()
+                            .unwrap_or(0);
+                    }
+
+                    if diag.severity == "note" {
+                        if let Some(current) = current.as_mut() {
+                            current.notes.push(diag.message);
+                            continue;
+                        }
+                    }
+
+                    if let Some(prev) = current.take() {
+                        outputs.push(CxxOutput::Remapped(prev));
+                    }
+
+                    current = Some(diag);
+                    forwarding = false;
+                }
+
+                // C++ excerpts and carets below remapped diagnostics are
+                // meaningless to esper users
+                None if !forwarding && line.starts_with(' ') => {}
+
+                _ => {
+                    if let Some(prev) = current.take() {
+                        outputs.push(CxxOutput::Remapped(prev));
+                    }
+
+                    outputs.push(CxxOutput::Forwarded(line.into()));
+                    forwarding = true;
+                }
+            }
+        }
+
+        if let Some(prev) = current.take() {
+            outputs.push(CxxOutput::Remapped(prev));
+        }
+
+        outputs
+    }
+}
+
+// stderr of the C++ compiler split into diagnostics against the esper
+// source and lines passed through as they are
+#[derive(Debug, Clone)]
+enum CxxOutput {
+    Remapped(ClangDiagnostic),
+    Forwarded(String),
+}
+
+// a single `file:line:col: severity: message` line from clang++ (or g++)
+#[derive(Debug, Clone)]
+struct ClangDiagnostic {
+    file: String,
+    line: usize,
+    severity: String,
+    message: String,
+    notes: Vec<String>,
+}
+
+impl ClangDiagnostic {
+    fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.splitn(5, ':');
+        let file = parts.next()?;
+        let line_num = parts.next()?.trim().parse::<usize>().ok()?;
+        let _column = parts.next()?.trim().parse::<usize>().ok()?;
+        let severity = parts.next()?.trim();
+        let message = parts.next()?.trim();
+
+        if !matches!(severity, "error" | "fatal error" | "warning" | "note") {
+            return None;
+        }
+
+        Some(ClangDiagnostic {
+            file: file.into(),
+            line: line_num,
+            severity: severity.into(),
+            message: message.into(),
+            notes: vec![],
+        })
+    }
+
+    // warnings carry the flag controlling them, e.g. `[-Wunused-variable]`
+    fn code(&self) -> Option<&str> {
+        let flag = self.message.strip_suffix(']')?.rsplit_once(" [")?.1;
+        flag.starts_with("-W").then_some(flag)
+    }
+
+    // the span covers the esper line the diagnostic was mapped to, lines
+    // past the end of the module are reported without a location
+    fn to_json(&self, esper_source: &SourceFile) -> String {
+        let lines = LineIndex::new(esper_source.source);
+        let severity = if self.severity == "fatal error" {
+            "error"
+        } else {
+            self.severity.as_str()
+        };
+
+        if self.line == 0 || self.line > lines.line_count() {
+            return json_object(
+                severity,
+                self.code(),
+                &self.message,
+                Some(esper_source.path),
+                None,
+                "",
+                &self.notes,
+            );
+        }
+
+        let line_start = lines.line_start(self.line);
+        let src_line = esper_source.source[line_start..]
+            .split('\n')
+            .next()
+            .unwrap_or("");
+        let indent = src_line.len() - src_line.trim_start().len();
+        let span = Span::new(line_start + indent, line_start + src_line.trim_end().len());
+
+        let diagnostic = if severity == "warning" {
+            Diagnostic::warning(&self.message, span)
+        } else {
+            Diagnostic::error(&self.message, span)
+        };
+
+        let diagnostic = self
+            .notes
+            .iter()
+            .fold(diagnostic, |diagnostic, note| diagnostic.with_note(note))
+            .with_label("in the C++ generated from this line");
+
+        match self.code() {
+            Some(code) => diagnostic.with_code(code),
+            None => diagnostic,
+        }
+        .to_json(esper_source.path, esper_source.source)
+    }
+
+    // columns refer to the generated C++ line, so the whole esper line is marked
+    fn render(&self, src_lines: &[&str]) -> String {
+        // past the end of the module, e.g. the generated main() wrapper
+        let Some(src_line) = self
+            .line
+            .checked_sub(1)
+            .and_then(|line| src_lines.get(line))
+        else {
+            let mut msg = format!("{}: {}\n", self.severity, self.message);
+
+            for note in &self.notes {
+                msg.push_str(&format!("  = note: {}\n", note));
+            }
+
+            return msg;
+        };
+
+        let start = src_line.chars().take_while(|c| c.is_whitespace()).count();
+        let end = src_line.trim_end().chars().count().max(start + 1);
+        let label = "in the C++ generated from this line";
+
+        let msg = if self.severity == "warning" {
+            let mut warning =
+                chic::Warning::new(&self.message).warning(self.line, start, end, src_line, label);
+
+            for note in &self.notes {
+                warning = warning.help(note);
+            }
+
+            warning.to_string()
+        } else {
+            let mut error =
+                chic::Error::new(&self.message).error(self.line, start, end, src_line, label);
+
+            for note in &self.notes {
+                error = error.help(note);
+            }
+
+            error.to_string()
+        };
 
-        Ok(())
+        format!("{}\n", msg)
     }
 }
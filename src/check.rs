@@ -0,0 +1,27 @@
+use crate::diagnostics::Diagnostic;
+use crate::exhaustive::Exhaustiveness;
+use crate::parser::Expr;
+use crate::resolve::Resolver;
+use crate::types::TypeChecker;
+
+// semantic passes run over a parsed program before any C++ is emitted,
+// `esper check` reports their diagnostics without invoking a C++ compiler
+// and `esper build` stops before emitting when one of them is an error
+pub trait Pass {
+    fn run(&self, program: &Expr, source: &str) -> Vec<Diagnostic>;
+}
+
+pub fn passes(allow_undefined: bool) -> Vec<Box<dyn Pass>> {
+    vec![
+        Box::new(Resolver { allow_undefined }),
+        Box::new(TypeChecker),
+        Box::new(Exhaustiveness),
+    ]
+}
+
+pub fn check_program(program: &Expr, source: &str, allow_undefined: bool) -> Vec<Diagnostic> {
+    passes(allow_undefined)
+        .iter()
+        .flat_map(|pass| pass.run(program, source))
+        .collect()
+}
@@ -0,0 +1,545 @@
+use crate::json::quote;
+use crate::parser::ParseError;
+use crate::span::{LineIndex, Span};
+
+use std::str::FromStr;
+
+// lines of context shown above and below the marked line
+const WINDOW_BEFORE: usize = 2;
+const WINDOW_AFTER: usize = 1;
+
+// binary operators are collapsed into a single entry of the expected set
+// since they can follow almost every expression
+const OPERATORS: &[&str] = &[
+    "+", "-", "*", "/", "and", "or", "eq", "neq", "gt", "lt", "gte", "lte", "band", "bor", "xor",
+    "shl", "shr", "rotl", "rotr",
+];
+
+// tokens starting an expression, collapsed when an expression is expected
+const EXPRESSION_START: &[&str] = &[
+    "`&`",
+    "`*`",
+    "`'`",
+    "`(`",
+    "`@`",
+    "`[`",
+    "`\"`",
+    "`false`",
+    "`for`",
+    "`if`",
+    "`let`",
+    "`match`",
+    "`pass`",
+    "`struct`",
+    "`true`",
+    "`type`",
+    "`{`",
+    "`|`",
+    "`~`",
+    "`-`",
+    "identifier",
+    "number",
+];
+
+// syntax errors reported by the parser
+pub const SYNTAX_ERROR: &str = "E0001";
+// variables without a definition in scope
+pub const UNDEFINED_NAME: &str = "E0002";
+// names defined twice in the same scope
+pub const DUPLICATE_DEFINITION: &str = "E0003";
+// values used where a type they do not convert to is expected
+pub const TYPE_MISMATCH: &str = "E0004";
+// calls with more or fewer arguments than parameters
+pub const ARGUMENT_COUNT: &str = "E0005";
+// matches on a variant without an arm for some of its alternatives
+pub const NON_EXHAUSTIVE_MATCH: &str = "E0006";
+// arms repeating an earlier pattern or matching no alternative
+pub const UNREACHABLE_ARM: &str = "E0007";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+// how diagnostics are written to stderr, json emits one object per line
+// for editors and CI annotators
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageFormat {
+    Human,
+    Json,
+}
+
+impl FromStr for MessageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(MessageFormat::Human),
+            "json" => Ok(MessageFormat::Json),
+            _ => Err(format!("expected `human` or `json`, found `{}`", s)),
+        }
+    }
+}
+
+// a problem found in an esper module, spans index into the module source
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<String>,
+    pub message: String,
+    pub span: Span,
+    pub label: String,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, span: Span) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code: None,
+            message: message.into(),
+            span,
+            label: String::new(),
+            notes: vec![],
+        }
+    }
+
+    pub fn warning(message: impl Into<String>, span: Span) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(message, span)
+        }
+    }
+
+    pub fn with_code(mut self, code: impl Into<String>) -> Self {
+        self.code = Some(code.into());
+        self
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = label.into();
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    // peg reports the furthest position it reached along with every literal
+    // or named rule that could have continued from there
+    pub fn from_parse_error(source: &str, peg_err: &ParseError) -> Self {
+        let mut offset = peg_err.location.offset.min(source.len());
+        let found = found_token(source, offset);
+
+        // point right after the last token rather than at trailing blank lines
+        if source[offset..].trim().is_empty() {
+            offset = source.trim_end().len();
+        }
+        let expected = expected_tokens(peg_err);
+
+        let message = match expected.as_slice() {
+            [] => format!("unexpected {}", found),
+            [single] => format!("expected {}, found {}", single, found),
+            [init @ .., last] => {
+                format!("expected {} or {}, found {}", init.join(", "), last, found)
+            }
+        };
+
+        let token_len = match source[offset..].chars().next() {
+            Some(c) if c.is_ascii_alphanumeric() || c == '_' => source[offset..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(source.len() - offset),
+            Some(c) if !c.is_whitespace() => c.len_utf8(),
+            _ => 0,
+        };
+
+        let label = match expected.as_slice() {
+            [single] => format!("expected {}", single),
+            _ => format!("unexpected {}", found),
+        };
+
+        Diagnostic::error(message, Span::new(offset, offset + token_len))
+            .with_code(SYNTAX_ERROR)
+            .with_label(label)
+    }
+
+    // renders a window of WINDOW_BEFORE lines above and WINDOW_AFTER lines
+    // below the line of the span, spans running past the end of that line
+    // are cut at the end of the line
+    pub fn render(&self, path: &str, source: &str) -> String {
+        let lines = LineIndex::new(source);
+        let src_lines: Vec<&str> = source.split('\n').collect();
+        let start = lines.line_col(source, self.span.start);
+        let end = lines.line_col(source, self.span.end);
+
+        let first_line = start.line.saturating_sub(WINDOW_BEFORE).max(1);
+        let last_line = (start.line + WINDOW_AFTER).min(src_lines.len());
+        let window = src_lines[first_line - 1..last_line]
+            .iter()
+            .map(|line| line.trim_end_matches('\r'))
+            .collect::<Vec<_>>();
+
+        // chic ranges are char offsets into the whole window, where its
+        // annotate-snippets backend advances by two for every line break
+        let line_offset = window[..start.line - first_line]
+            .iter()
+            .map(|line| line.chars().count() + 2)
+            .sum::<usize>();
+        let line_len = window[start.line - first_line].chars().count();
+        let col_start = start.column - 1;
+        let col_end = if end.line == start.line {
+            end.column - 1
+        } else {
+            line_len
+        }
+        .max(col_start + 1);
+
+        let range = (line_offset + col_start, line_offset + col_end);
+        let src = window.join("\n");
+
+        let rendered = match self.severity {
+            Severity::Error => {
+                let mut error = chic::Error::new(&self.message).error(
+                    first_line,
+                    range.0,
+                    range.1,
+                    &src,
+                    &self.label,
+                );
+
+                for note in &self.notes {
+                    error = error.help(note);
+                }
+
+                error.to_string()
+            }
+
+            Severity::Warning => {
+                let mut warning = chic::Warning::new(&self.message).warning(
+                    first_line,
+                    range.0,
+                    range.1,
+                    &src,
+                    &self.label,
+                );
+
+                for note in &self.notes {
+                    warning = warning.help(note);
+                }
+
+                warning.to_string()
+            }
+        };
+
+        // the location goes right below the title line
+        match rendered.split_once('\n') {
+            Some((title, snippet)) => format!(
+                "{}\n  --> {}:{}:{}\n{}",
+                title, path, start.line, start.column, snippet
+            ),
+            None => rendered,
+        }
+    }
+
+    pub fn emit(&self, format: MessageFormat, path: &str, source: &str) -> String {
+        match format {
+            MessageFormat::Human => self.render(path, source),
+            MessageFormat::Json => self.to_json(path, source),
+        }
+    }
+
+    // lines and columns are 1-based like in the rendered form, columns
+    // count chars while start and end are byte offsets into the source
+    pub fn to_json(&self, path: &str, source: &str) -> String {
+        let lines = LineIndex::new(source);
+        let start = lines.line_col(source, self.span.start);
+        let end = lines.line_col(source, self.span.end);
+
+        let span = format!(
+            "{{\"start\":{},\"end\":{},\"line\":{},\"column\":{},\"end_line\":{},\"end_column\":{}}}",
+            self.span.start, self.span.end, start.line, start.column, end.line, end.column
+        );
+
+        json_object(
+            self.severity.as_str(),
+            self.code.as_deref(),
+            &self.message,
+            Some(path),
+            Some(&span),
+            &self.label,
+            &self.notes,
+        )
+    }
+}
+
+// every diagnostic reported for a module, in the order they were found
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Diagnostics(vec![])
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.0.push(diagnostic);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.iter()
+            .filter(|diagnostic| diagnostic.is_error())
+            .count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.error_count() > 0
+    }
+}
+
+impl From<Vec<Diagnostic>> for Diagnostics {
+    fn from(diagnostics: Vec<Diagnostic>) -> Self {
+        Diagnostics(diagnostics)
+    }
+}
+
+impl IntoIterator for Diagnostics {
+    type Item = Diagnostic;
+    type IntoIter = std::vec::IntoIter<Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+// messages without a location in an esper module, e.g. I/O failures,
+// linker output or the summary after all diagnostics
+pub fn render_message(
+    format: MessageFormat,
+    severity: &str,
+    file: Option<&str>,
+    message: &str,
+) -> String {
+    match (format, file) {
+        (MessageFormat::Human, Some(file)) => format!("{}: {}: {}", severity, file, message),
+        (MessageFormat::Human, None) => format!("{}: {}", severity, message),
+        (MessageFormat::Json, _) => json_object(severity, None, message, file, None, "", &[]),
+    }
+}
+
+// the span is an already serialized object, null when there is none
+pub fn json_object(
+    severity: &str,
+    code: Option<&str>,
+    message: &str,
+    file: Option<&str>,
+    span: Option<&str>,
+    label: &str,
+    notes: &[String],
+) -> String {
+    format!(
+        "{{\"severity\":{},\"code\":{},\"message\":{},\"file\":{},\"span\":{},\"label\":{},\"notes\":[{}]}}",
+        quote(severity),
+        code.map_or("null".into(), quote),
+        quote(message),
+        file.map_or("null".into(), quote),
+        span.unwrap_or("null"),
+        quote(label),
+        notes
+            .iter()
+            .map(|note| quote(note))
+            .collect::<Vec<_>>()
+            .join(",")
+    )
+}
+
+// describes the expected set in terms of the language rather than the
+// grammar, e.g. `"end"` becomes `end` and char classes become "number"
+fn expected_tokens(peg_err: &ParseError) -> Vec<String> {
+    let mut tokens = vec![];
+
+    for token in peg_err.expected.tokens() {
+        let described = if token == "\"(*\"" {
+            // comments can appear anywhere
+            continue;
+        } else if token == "EOF" {
+            "end of file".to_string()
+        } else if token.starts_with('"') && token.ends_with('"') && token.len() > 1 {
+            let literal = token[1..token.len() - 1]
+                .replace("\\\"", "\"")
+                .replace("\\'", "'")
+                .replace("\\\\", "\\");
+
+            format!("`{}`", literal)
+        } else if token.starts_with('[') {
+            if token.contains("'0'") {
+                "number".to_string()
+            } else {
+                "character".to_string()
+            }
+        } else {
+            token.to_string()
+        };
+
+        if !tokens.contains(&described) {
+            tokens.push(described);
+        }
+    }
+
+    // any expression is accepted when both identifiers and numbers are,
+    // this also covers prefix operators such as `-` and `*`
+    let mut has_expression = false;
+
+    if tokens.iter().any(|t| t == "identifier") && tokens.iter().any(|t| t == "number") {
+        tokens.retain(|t| !EXPRESSION_START.contains(&t.as_str()));
+        has_expression = true;
+    }
+
+    let operator_count = tokens.len();
+    tokens.retain(|t| !OPERATORS.contains(&t.trim_matches('`')));
+    let has_operator = tokens.len() != operator_count;
+
+    tokens.sort();
+
+    if has_expression {
+        tokens.push("expression".to_string());
+    }
+
+    if has_operator {
+        tokens.push("operator".to_string());
+    }
+
+    tokens
+}
+
+fn found_token(source: &str, offset: usize) -> String {
+    let rest = &source[offset..];
+
+    match rest.chars().next() {
+        None => "end of file".to_string(),
+        Some('\n') | Some('\r') => "end of line".to_string(),
+        Some(c) if c.is_whitespace() => "whitespace".to_string(),
+        Some(c) if c.is_ascii_alphanumeric() || c == '_' => {
+            let word_end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            format!("`{}`", &rest[..word_end])
+        }
+        Some(c) => format!("`{}`", c),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::span::LineCol;
+
+    // the first syntax error of a module with its 1-based line and column
+    fn syntax_error(source: &str) -> (Diagnostic, LineCol) {
+        let diagnostic = match crate::parse(source) {
+            Err(diagnostics) => diagnostics.0[0].clone(),
+            Ok(_) => panic!("`{}` parsed", source),
+        };
+        let start = LineIndex::new(source).line_col(source, diagnostic.span.start);
+
+        (diagnostic, start)
+    }
+
+    #[test]
+    fn columns_are_relative_to_the_line() {
+        let (_, start) = syntax_error("let x = 1\nlet y = 2 $ 3\n");
+        assert_eq!(
+            start,
+            LineCol {
+                line: 2,
+                column: 11
+            }
+        );
+    }
+
+    #[test]
+    fn errors_on_the_only_line_are_on_line_one() {
+        let source = "let x = 1 $ 2";
+        let (diagnostic, start) = syntax_error(source);
+
+        assert_eq!(
+            start,
+            LineCol {
+                line: 1,
+                column: 11
+            }
+        );
+        assert!(diagnostic
+            .render("a.esp", source)
+            .contains("--> a.esp:1:11"));
+    }
+
+    #[test]
+    fn end_of_file_points_after_the_last_token() {
+        let (diagnostic, start) = syntax_error("let x = (1 +\n\n\n");
+
+        assert!(diagnostic.message.ends_with("found end of file"));
+        assert_eq!(
+            start,
+            LineCol {
+                line: 1,
+                column: 13
+            }
+        );
+        assert!(diagnostic.span.is_empty());
+    }
+
+    #[test]
+    fn columns_count_chars_not_bytes() {
+        let source = "let s = \"é→\" $ 1";
+        let (diagnostic, start) = syntax_error(source);
+
+        assert_eq!(
+            start,
+            LineCol {
+                line: 1,
+                column: 14
+            }
+        );
+        assert!(diagnostic
+            .to_json("a.esp", source)
+            .contains("\"column\":14"));
+    }
+
+    #[test]
+    fn expected_tokens_are_described_in_the_language() {
+        let (diagnostic, _) = syntax_error("let x = 1 $ 2");
+
+        assert_eq!(
+            diagnostic.message,
+            "expected end of file, expression or operator, found `$`"
+        );
+        assert_eq!(diagnostic.label, "unexpected `$`");
+    }
+}
@@ -1,6 +1,11 @@
-use crate::parser::{BinOp, BitOp, CompareOp, Expr, UnaryOp};
+use crate::cc::CxxFlavor;
+use crate::parser::{method, BinOp, BitOp, CompareOp, Expr, ExprKind, UnaryOp};
+use crate::span::{LineIndex, Span};
 use crate::visit::{EsperContext, Visitor};
 
+use std::collections::HashMap;
+use std::str::FromStr;
+
 // note: esper outputs with some non-practical patterns:
 // 1 - where GLIBXX is not defined or not in /usr/include/c++, we conditionally
 //     include libstdc++ headers since we will only be compiling with clang++
@@ -8,12 +13,54 @@ use crate::visit::{EsperContext, Visitor};
 // 3 - public class member definitions (leaky abstractions) is forced
 // 4 - C++ initializer list for RHS list-like expressions
 
+// how lambdas capture the locals of the enclosing function, lambdas
+// capturing by value are `mutable` so that their copies can be assigned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capture {
+    Reference,
+    Value,
+}
+
+impl FromStr for Capture {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reference" => Ok(Capture::Reference),
+            "value" => Ok(Capture::Value),
+            _ => Err(format!("expected `reference` or `value`, found `{}`", s)),
+        }
+    }
+}
+
+// type parameters and fields of a struct, in declaration order
+pub type StructFields = (Vec<Expr>, Vec<(String, Expr)>);
+
 #[derive(Debug, Clone)]
 pub struct EmitContextImpl {
     pub level: usize,
     pub output: String,
     pub module_id: String,
     pub use_prelude: bool,
+    pub cxx_flavor: CxxFlavor,
+    pub exhaustive_match: bool,
+    pub capture: Capture,
+    // number of function bodies around the current expression, functions
+    // defined within one are emitted as lambdas
+    pub fn_depth: usize,
+    // fields of the structs of the program in declaration order, records
+    // initializing them are passed to their constructor in that order
+    pub structs: HashMap<String, StructFields>,
+    // when set, statements are preceded by #line directives pointing
+    // back into the esper source so C++ diagnostics refer to it
+    pub line_map: Option<(String, LineIndex)>,
+    pending_line: Option<usize>,
+}
+
+impl Default for EmitContextImpl {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl EmitContextImpl {
@@ -23,14 +70,48 @@ impl EmitContextImpl {
             output: String::new(),
             module_id: String::new(),
             use_prelude: false,
+            cxx_flavor: CxxFlavor::Clang,
+            exhaustive_match: false,
+            capture: Capture::Reference,
+            fn_depth: 0,
+            structs: HashMap::new(),
+            line_map: None,
+            pending_line: None,
         }
     }
 
     pub fn emit(&mut self, code: &str) {
+        let code = &indent_continuation(code);
+
+        // the directive goes right before the first non-blank line
+        // emitted for the statement, leading newlines are kept above it
+        let code_start = code.len() - code.trim_start_matches('\n').len();
+
+        if code_start < code.len() {
+            if let (Some(line), Some((file, _))) = (self.pending_line.take(), &self.line_map) {
+                let file = file.replace('\\', "\\\\").replace('"', "\\\"");
+                let directive = format!("#line {} \"{}\"\n", line, file);
+
+                self.output.push_str(&code[..code_start]);
+                self.output.push_str(&directive);
+                self.output.push_str(&code[code_start..]);
+                self.output.push('\n');
+                return;
+            }
+        }
+
         self.output.push_str(code);
         self.output.push('\n');
     }
 
+    pub fn mark_line(&mut self, span: Span) {
+        if let Some((_, lines)) = &self.line_map {
+            if !span.is_empty() {
+                self.pending_line = Some(lines.line(span.start));
+            }
+        }
+    }
+
     pub fn indent(&mut self) -> String {
         " ".repeat(self.level)
     }
@@ -49,6 +130,32 @@ pub struct EmitDefault {
 
 impl EmitDefault {
     pub fn emit_program(&mut self, expr: &Expr, module_id: &str) -> String {
+        let structs = &mut self.ctx.structs;
+
+        expr.visit(
+            &mut EmitContextImpl::new(),
+            &mut |_, expr| match &expr.kind {
+                ExprKind::Struct(name, ty_params, entries) => {
+                    let fields = entries
+                        .iter()
+                        .filter(|(_, entry)| method(entry).is_none())
+                        .cloned()
+                        .collect();
+
+                    structs.insert(name.clone(), (ty_params.clone(), fields));
+                }
+
+                // record aliases are structs of their fields without methods
+                ExprKind::TypeAlias(name, ty_params, rhs) => {
+                    if let Some(fields) = record_alias_fields(rhs) {
+                        structs.insert(name.clone(), (ty_params.clone(), fields));
+                    }
+                }
+
+                _ => {}
+            },
+        );
+
         let mut ctx = self.ctx.clone();
 
         ctx.module_id = module_id.into();
@@ -57,8 +164,12 @@ impl EmitDefault {
     }
 
     pub fn emit_expr(&self, ctx: &mut EmitContextImpl, expr: &Expr) {
-        match expr {
-            Expr::Program(exprs) => {
+        if !matches!(expr.kind, ExprKind::Program(_)) {
+            ctx.mark_line(expr.span);
+        }
+
+        match &expr.kind {
+            ExprKind::Program(exprs) => {
                 if ctx.use_prelude {
                     ctx.emit(include_str!("./prelude.h"));
                 }
@@ -81,55 +192,28 @@ impl EmitDefault {
                 );
             }
 
-            Expr::Let(var, value) => {
+            ExprKind::Let(var, ty_params, value) => {
                 let indent = ctx.indent();
 
-                match value.as_ref() {
-                    Expr::Fn(params, body) => {
-                        ctx.emit("");
-                        let params_str = params
-                            .iter()
-                            .map(|(param, ty)| match ty {
-                                Some(ty) => format!("{} {}", self.emit_type(ty), param),
-                                None => param.clone(),
-                            })
-                            .collect::<Vec<_>>()
-                            .join(", ");
-
-                        ctx.emit(&format!("{}auto {}({}) {{", indent, var, params_str));
-                        ctx.level += 2;
-
-                        let last = body.last(); /* keep before redefinition */
-                        let body: &Vec<Expr> = &body[0..body.len() - 1].into();
-
-                        for expr in body {
-                            self.emit_expr(ctx, expr);
-                        }
+                match &value.kind {
+                    // only functions at namespace scope are definitions
+                    ExprKind::Fn(params, body) if ctx.fn_depth > 0 => {
+                        self.emit_lambda(ctx, var, ty_params, params, None, body);
+                    }
 
-                        if let Some(last) = last {
-                            if matches!(
-                                last,
-                                Expr::Int(_)
-                                    | Expr::Float(_)
-                                    | Expr::Bool(_)
-                                    | Expr::Char(_)
-                                    | Expr::String(_)
-                                    | Expr::Var(_)
-                                    | Expr::Bin(_, _, _)
-                                    | Expr::Compare(_, _, _)
-                                    | Expr::List(_)
-                                    | Expr::Member(_)
-                                    | Expr::Range(_, _)
-                                    | Expr::Call(_, _)
-                                    | Expr::Pass
-                                    | Expr::TypedCall(_, _, _)
-                            ) {
-                                let indent = ctx.indent();
-                                ctx.emit(&format!("{}return {};", indent, self.emit_value(last)));
-                            }
-                        }
+                    ExprKind::Fn(params, body) => {
+                        ctx.emit("");
+                        let params_str = self.emit_params(params).join(", ");
 
-                        ctx.level -= 2;
+                        self.emit_template(ctx, ty_params);
+                        ctx.emit(&format!(
+                            "{}{} {}({}) {{",
+                            indent,
+                            deduced_return_type(body),
+                            var,
+                            params_str
+                        ));
+                        self.emit_fn_body(ctx, body, None);
                         ctx.emit(&format!("{}}}", indent));
                     }
                     _ => {
@@ -137,65 +221,31 @@ impl EmitDefault {
                             "{}auto {} = {};",
                             indent,
                             var,
-                            self.emit_value(value)
+                            self.emit_value(ctx, value)
                         ));
                     }
                 }
             }
 
-            Expr::TypedLet(var, ty, value) => {
+            ExprKind::TypedLet(var, ty_params, ty, value) => {
                 let indent = ctx.indent();
 
-                match value.as_ref() {
-                    Expr::Fn(params, body) => {
+                match &value.kind {
+                    ExprKind::Fn(params, body) if ctx.fn_depth > 0 => {
+                        self.emit_lambda(ctx, var, ty_params, params, Some(ty), body);
+                    }
+
+                    ExprKind::Fn(params, body) => {
                         ctx.emit("");
-                        let params_str = params
-                            .iter()
-                            .map(|(param, ty)| match ty {
-                                Some(ty) => format!("{} {}", self.emit_type(ty), param),
-                                None => param.clone(),
-                            })
-                            .collect::<Vec<_>>()
-                            .join(", ");
+                        let params_str = self.emit_params(params).join(", ");
                         let return_type = self.emit_type(ty);
 
+                        self.emit_template(ctx, ty_params);
                         ctx.emit(&format!(
                             "{}{} {}({}) {{",
                             indent, return_type, var, params_str
                         ));
-                        ctx.level += 2;
-
-                        let last = body.last();
-                        let body: &Vec<Expr> = &body[0..body.len() - 1].into();
-
-                        for expr in body {
-                            self.emit_expr(ctx, expr);
-                        }
-
-                        if let Some(last) = last {
-                            if matches!(
-                                last,
-                                Expr::Int(_)
-                                    | Expr::Float(_)
-                                    | Expr::Bool(_)
-                                    | Expr::Char(_)
-                                    | Expr::String(_)
-                                    | Expr::Var(_)
-                                    | Expr::Bin(_, _, _)
-                                    | Expr::Compare(_, _, _)
-                                    | Expr::List(_)
-                                    | Expr::Member(_)
-                                    | Expr::Range(_, _)
-                                    | Expr::Call(_, _)
-                                    | Expr::Pass
-                                    | Expr::TypedCall(_, _, _)
-                            ) {
-                                let indent = ctx.indent();
-                                ctx.emit(&format!("{}return {};", indent, self.emit_value(last)));
-                            }
-                        }
-
-                        ctx.level -= 2;
+                        self.emit_fn_body(ctx, body, Some(ty));
                         ctx.emit(&format!("{}}}", indent));
                     }
                     _ => {
@@ -205,56 +255,33 @@ impl EmitDefault {
                             indent,
                             ty_str,
                             var,
-                            self.emit_value(value)
+                            self.emit_value_as(ctx, value, Some(ty))
                         ));
                     }
                 }
             }
 
-            Expr::Assign(lhs, rhs) => {
+            ExprKind::Assign(lhs, rhs) => {
                 let indent = ctx.indent();
 
                 ctx.emit(&format!(
                     "{}{} = {};",
                     indent,
-                    self.emit_value(lhs),
-                    self.emit_value(rhs)
+                    self.emit_value(ctx, lhs),
+                    self.emit_value(ctx, rhs)
                 ));
             }
 
-            Expr::If(cond, then_body, else_body) => {
-                let cond_str = self.emit_value(cond);
-                let indent = ctx.indent();
-
-                ctx.emit(&format!("{}if ({}) {{", indent, cond_str));
-                ctx.level += 2;
-
-                for expr in then_body {
-                    self.emit_expr(ctx, expr);
-                }
-
-                ctx.level -= 2;
-                ctx.emit(&format!("{}}}", indent));
-
-                if let Some(else_body) = else_body {
-                    ctx.emit(&format!("{}else {{", indent));
-                    ctx.level += 2;
-
-                    for expr in else_body {
-                        self.emit_expr(ctx, expr);
-                    }
-
-                    ctx.level -= 2;
-                    ctx.emit(&format!("{}}}", indent));
-                }
+            ExprKind::If(cond, then_body, else_body) => {
+                self.emit_if(ctx, cond, then_body, else_body.as_deref(), None);
             }
 
-            Expr::Loop(loop_var, iter_expr, body) => {
-                let mut loop_var_str = self.emit_value(loop_var);
-                let iter_str = self.emit_value(iter_expr);
+            ExprKind::Loop(loop_var, iter_expr, body) => {
+                let mut loop_var_str = self.emit_value(ctx, loop_var);
+                let iter_str = self.emit_value(ctx, iter_expr);
                 let indent = ctx.indent();
 
-                if (matches!(*loop_var.to_owned(), Expr::List(_))) {
+                if matches!(loop_var.kind, ExprKind::List(_)) {
                     loop_var_str.replace_range(0..1, "[");
                     loop_var_str.replace_range(loop_var_str.len() - 1..loop_var_str.len(), "]");
                 }
@@ -274,136 +301,102 @@ impl EmitDefault {
                 ctx.emit(&format!("{}}}", indent));
             }
 
-            Expr::Match(cond, cases) => {
-                let cond_str = self.emit_value(cond);
-                let indent = ctx.indent();
-
-                ctx.emit(&format!("{}std::visit([](auto&& _) {{", indent));
-                ctx.level += 2;
-                let indent = ctx.indent();
-                ctx.emit(&format!("{}using T = std::decay_t<decltype(_)>;", indent));
-
-                // ctx.level += 2;
-
-                for (pat, body) in cases {
-                    let indent = ctx.indent();
-                    let pat_str = self.emit_value(&Expr::Var(pat.clone()));
-
-                    ctx.emit(&format!(
-                        "{}if constexpr (std::is_same_v<T, {}>) {{",
-                        indent, pat_str
-                    ));
-                    ctx.level += 2;
-
-                    for expr in body {
-                        self.emit_expr(ctx, expr);
-                    }
-
-                    ctx.level -= 2;
-                    ctx.emit(&format!("{}}}", indent));
-                }
-
-                ctx.level -= 2;
-                ctx.emit(&format!("{}}}, {});", indent, cond_str));
+            ExprKind::Match(cond, cases) => {
+                self.emit_match(ctx, cond, cases, None);
             }
 
-            Expr::Struct(name, entries) => {
+            ExprKind::Struct(name, ty_params, entries) => {
                 let indent = ctx.indent();
-                ctx.emit(&format!("\nclass {} {{", name));
+                ctx.emit("");
+                self.emit_template(ctx, ty_params);
+                ctx.emit(&format!("{}class {} {{", indent, name));
                 ctx.emit("public:");
                 ctx.level += 2;
 
-                for (field_name, expr) in entries {
-                    match expr {
-                        Expr::TypedSymbol(_) | Expr::TypedLiteral(_) => {
-                            let indent = ctx.indent();
-                            let field_ty = self.emit_type(expr);
-
-                            ctx.emit(&format!("{}{} {};", indent, field_ty, field_name));
-                        }
-
-                        Expr::Fn(params, body) => {
-                            let indent = ctx.indent();
-                            let params_str = params
-                                .iter()
-                                .map(|(param, ty)| match ty {
-                                    Some(ty) => format!("{} {}", self.emit_type(ty), param),
-                                    None => param.clone(),
-                                })
-                                .collect::<Vec<_>>()
-                                .join(", ");
+                let fields = entries
+                    .iter()
+                    .filter(|(_, expr)| method(expr).is_none())
+                    .collect::<Vec<_>>();
 
-                            // @todo: method return type
+                for (field_name, ty) in &fields {
+                    let indent = ctx.indent();
+                    ctx.emit(&format!("{}{} {};", indent, self.emit_type(ty), field_name));
+                }
 
-                            ctx.emit(&format!("{}auto {}({}) {{", indent, field_name, params_str));
-                            ctx.level += 2;
+                // member-wise, fields not passed are value initialized
+                if !fields.is_empty() {
+                    let indent = ctx.indent();
+                    let params_str = fields
+                        .iter()
+                        .map(|(field_name, ty)| {
+                            format!("{} {} = {{}}", self.emit_type(ty), field_name)
+                        })
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    let init_str = fields
+                        .iter()
+                        .map(|(field_name, _)| format!("{}({})", field_name, field_name))
+                        .collect::<Vec<_>>()
+                        .join(", ");
 
-                            let last = body.last();
-                            let body: &Vec<Expr> = &body[0..body.len() - 1].into();
+                    ctx.emit(&format!(
+                        "{}{}({}) : {} {{}}",
+                        indent, name, params_str, init_str
+                    ));
+                }
 
-                            for expr in body {
-                                self.emit_expr(ctx, expr);
-                            }
+                for (method_name, expr) in entries {
+                    let Some((params, body, return_type)) = method(expr) else {
+                        continue;
+                    };
 
-                            if let Some(last) = last {
-                                let indent = ctx.indent();
-                                ctx.emit(&format!("{}return {};", indent, self.emit_value(last)));
-                            }
+                    let indent = ctx.indent();
+                    let params_str = self.emit_params(params).join(", ");
+                    let return_type_str = match return_type {
+                        Some(ty) => self.emit_type(ty),
+                        None => deduced_return_type(body).to_string(),
+                    };
 
-                            ctx.level -= 2;
-                            ctx.emit(&format!("{}}}", indent));
-                        }
-                        _ => {}
-                    }
+                    ctx.emit(&format!(
+                        "{}{} {}({}) {{",
+                        indent, return_type_str, method_name, params_str
+                    ));
+                    self.emit_fn_body(ctx, body, return_type);
+                    ctx.emit(&format!("{}}}", indent));
                 }
 
                 ctx.level -= 2;
                 ctx.emit(&format!("{}}};", indent));
             }
 
-            Expr::TypeAlias(name, ty_params, rhs) => {
+            ExprKind::TypeAlias(name, ty_params, rhs) => {
                 let indent = ctx.indent();
 
                 let template_str = if ty_params.is_empty() {
                     String::new()
                 } else {
-                    let ty_params_str = ty_params
-                        .iter()
-                        .map(|ty| format!("typename {}", self.emit_type(ty)))
-                        .collect::<Vec<_>>()
-                        .join(", ");
-                    format!("template<{}> ", ty_params_str)
+                    format!("template<{}> ", self.emit_template_params(ty_params))
                 };
 
-                match rhs.as_ref() {
-                    Expr::TypedSymbol(ty) => {
+                match &rhs.kind {
+                    ExprKind::TypedSymbol(ty) => {
                         ctx.emit(&format!(
                             "{}{}using {} = {};",
                             indent, template_str, name, ty
                         ));
                     }
 
-                    Expr::TypedRecord(record_expr) => {
-                        if let Expr::Record(entries) = record_expr.as_ref() {
-                            ctx.emit(&format!("{}{}struct {} {{", indent, template_str, name));
-                            ctx.level += 2;
-
-                            for entry in entries {
-                                if entry.len() == 2 {
-                                    let indent = ctx.indent();
-                                    let key_str = self.emit_value(&entry[0]);
-                                    let value_str = self.emit_type(&entry[1]);
-
-                                    ctx.emit(&format!(
-                                        "{}using {} = {};",
-                                        indent, key_str, value_str
-                                    ));
-                                }
-                            }
+                    ExprKind::TypedRecord(_) => {
+                        ctx.emit(&format!("{}{}struct {} {{", indent, template_str, name));
+                        ctx.level += 2;
 
-                            ctx.level -= 2;
-                            ctx.emit(&format!("{}}};", indent));
+                        for (field, ty) in record_alias_fields(rhs).unwrap_or_default() {
+                            let indent = ctx.indent();
+                            ctx.emit(&format!("{}{} {};", indent, self.emit_type(&ty), field));
                         }
+
+                        ctx.level -= 2;
+                        ctx.emit(&format!("{}}};", indent));
                     }
 
                     _ => {
@@ -418,39 +411,36 @@ impl EmitDefault {
                 }
             }
 
-            Expr::Directive(directive, expr) => {
-                if let Expr::Call(callee, args) = directive.as_ref() {
-                    if let Expr::Var(directive_name) = callee.as_ref() {
+            ExprKind::Directive(directive, expr) => {
+                if let ExprKind::Call(callee, args) = &directive.kind {
+                    if let ExprKind::Var(directive_name) = &callee.kind {
                         if directive_name == "extend" {
-                            if let [Expr::Var(ident), Expr::Var(ty)] = &args[..] {
-                                self.emit_extend(ctx, ident, ty, expr);
+                            if let [ident, ty] = &args[..] {
+                                if let (ExprKind::Var(ident), ExprKind::Var(ty)) =
+                                    (&ident.kind, &ty.kind)
+                                {
+                                    self.emit_extend(ctx, ident, ty, expr);
+                                }
                             }
                         }
                     }
                 } else {
                     // only go through modifiers that are non-call exprs
-                    let out = self.emit_value(&Expr::Directive(
-                        Box::new(directive.as_ref().to_owned()),
-                        Box::new(expr.as_ref().to_owned()),
-                    ));
+                    let out = self.emit_value(
+                        ctx,
+                        &Expr::new(
+                            ExprKind::Directive(
+                                Box::new(directive.as_ref().to_owned()),
+                                Box::new(expr.as_ref().to_owned()),
+                            ),
+                            directive.span.to(expr.span),
+                        ),
+                    );
 
-                    if matches!(
-                        **expr,
-                        Expr::Int(_)
-                            | Expr::Float(_)
-                            | Expr::Bool(_)
-                            | Expr::Char(_)
-                            | Expr::String(_)
-                            | Expr::Var(_)
-                            | Expr::Bin(_, _, _)
-                            | Expr::Compare(_, _, _)
-                            | Expr::List(_)
-                            | Expr::Member(_)
-                            | Expr::Range(_, _)
-                            | Expr::Call(_, _)
-                            | Expr::Pass
-                            | Expr::TypedCall(_, _, _)
-                    ) {
+                    let is_statement =
+                        matches!(expr.kind, ExprKind::If(_, _, _) | ExprKind::Match(_, _));
+
+                    if (expr.is_value() && !is_statement) || matches!(expr.kind, ExprKind::Pass) {
                         // modifiers on expressions (emit_value)
                         let indent = ctx.indent();
                         ctx.emit(&format!("{}{};", indent, out));
@@ -465,31 +455,326 @@ impl EmitDefault {
 
             _ => {
                 let indent = ctx.indent();
-                ctx.emit(&format!("{}{};", indent, &self.emit_value(expr)));
+                ctx.emit(&format!("{}{};", indent, &self.emit_value(ctx, expr)));
+            }
+        }
+    }
+
+    // statements of a function body, the last one is returned when it is
+    // a value (of the declared return type, if any)
+    fn emit_fn_body(&self, ctx: &mut EmitContextImpl, body: &[Expr], return_type: Option<&Expr>) {
+        ctx.level += 2;
+        ctx.fn_depth += 1;
+        self.emit_tail(ctx, body, return_type);
+        ctx.fn_depth -= 1;
+        ctx.level -= 2;
+    }
+
+    // a block in tail position returns its last value, a last `if` or
+    // `match` returns from each of its branches instead
+    fn emit_tail(&self, ctx: &mut EmitContextImpl, body: &[Expr], return_type: Option<&Expr>) {
+        let Some((last, body)) = body.split_last() else {
+            return;
+        };
+
+        for expr in body {
+            self.emit_expr(ctx, expr);
+        }
+
+        match &last.kind {
+            ExprKind::If(cond, then_body, else_body) => {
+                ctx.mark_line(last.span);
+                self.emit_if(
+                    ctx,
+                    cond,
+                    then_body,
+                    else_body.as_deref(),
+                    Some(return_type),
+                );
+            }
+
+            ExprKind::Match(cond, cases) => {
+                ctx.mark_line(last.span);
+                self.emit_match(ctx, cond, cases, Some(return_type));
+            }
+
+            // modifiers have no meaning on a returned value
+            ExprKind::Directive(_, inner) if inner.is_value() => {
+                self.emit_tail(ctx, std::slice::from_ref(inner), return_type);
+            }
+
+            ExprKind::Pass => {}
+
+            _ if last.is_value() => {
+                let indent = ctx.indent();
+                ctx.mark_line(last.span);
+                ctx.emit(&format!(
+                    "{}return {};",
+                    indent,
+                    self.emit_value_as(ctx, last, return_type)
+                ));
+            }
+
+            _ => self.emit_expr(ctx, last),
+        }
+    }
+
+    // branches return their last value in tail position
+    fn emit_branch(&self, ctx: &mut EmitContextImpl, body: &[Expr], tail: Option<Option<&Expr>>) {
+        ctx.level += 2;
+
+        match tail {
+            Some(return_type) => self.emit_tail(ctx, body, return_type),
+            None => {
+                for expr in body {
+                    self.emit_expr(ctx, expr);
+                }
             }
         }
+
+        ctx.level -= 2;
     }
 
-    fn emit_value(&self, expr: &Expr) -> String {
-        match expr {
-            Expr::Int(n) => n.to_string(),
-            Expr::Float(f) => f.to_string(),
-            Expr::Bool(b) => {
+    fn emit_if(
+        &self,
+        ctx: &mut EmitContextImpl,
+        cond: &Expr,
+        then_body: &[Expr],
+        else_body: Option<&[Expr]>,
+        tail: Option<Option<&Expr>>,
+    ) {
+        let cond_str = self.emit_value(ctx, cond);
+        let indent = ctx.indent();
+
+        ctx.emit(&format!("{}if ({}) {{", indent, cond_str));
+        self.emit_branch(ctx, then_body, tail);
+        ctx.emit(&format!("{}}}", indent));
+
+        if let Some(else_body) = else_body {
+            ctx.emit(&format!("{}else {{", indent));
+            self.emit_branch(ctx, else_body, tail);
+            ctx.emit(&format!("{}}}", indent));
+        }
+    }
+
+    // one `if constexpr` per arm on the type of the matched value, the
+    // visitor captures like any other lambda
+    fn emit_match(
+        &self,
+        ctx: &mut EmitContextImpl,
+        cond: &Expr,
+        cases: &[(String, Vec<Expr>)],
+        tail: Option<Option<&Expr>>,
+    ) {
+        let cond_str = self.emit_value(ctx, cond);
+        let indent = ctx.indent();
+        // arms of a declared return type may return different types that
+        // the visitor converts, std::visit needs one return type
+        let return_type = tail.flatten().map(|ty| self.emit_type(ty));
+        let head =
+            self.emit_lambda_head(ctx, &[], &["auto&& _".to_string()], return_type.as_deref());
+        let return_str = if tail.is_some() { "return " } else { "" };
+
+        ctx.emit(&format!("{}{}std::visit({}", indent, return_str, head));
+        ctx.level += 2;
+        let inner_indent = ctx.indent();
+        ctx.emit(&format!(
+            "{}using T = std::decay_t<decltype(_)>;",
+            inner_indent
+        ));
+
+        // arms are chained so only the first arm of a type runs, as in the
+        // interpreter, the assertion (when the match must be exhaustive)
+        // only fails for a type no arm was instantiated for
+        for (i, (pat, body)) in cases.iter().enumerate() {
+            let pat_str = self.emit_value(ctx, &ExprKind::Var(pat.clone()).into());
+
+            ctx.emit(&format!(
+                "{}{}if constexpr (std::is_same_v<T, {}>) {{",
+                inner_indent,
+                if i == 0 { "" } else { "else " },
+                pat_str
+            ));
+            self.emit_branch(ctx, body, tail);
+            ctx.emit(&format!("{}}}", inner_indent));
+        }
+
+        if ctx.exhaustive_match {
+            let chain = if cases.is_empty() { "" } else { "else " };
+
+            ctx.emit(&format!(
+                "{}{}static_assert(!std::is_same_v<T, T>, \"non-exhaustive match\");",
+                inner_indent, chain
+            ));
+        }
+
+        ctx.level -= 2;
+        ctx.emit(&format!("{}}}, {});", indent, cond_str));
+    }
+
+    // generic functions and structs are templates over their type parameters
+    fn emit_template(&self, ctx: &mut EmitContextImpl, ty_params: &[Expr]) {
+        if !ty_params.is_empty() {
+            let indent = ctx.indent();
+            let params_str = self.emit_template_params(ty_params);
+            ctx.emit(&format!("{}template<{}>", indent, params_str));
+        }
+    }
+
+    pub fn emit_template_params(&self, ty_params: &[Expr]) -> String {
+        ty_params
+            .iter()
+            .map(|ty| format!("typename {}", self.emit_type(ty)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    // functions within functions are lambdas capturing by reference, a
+    // recursive one takes itself as its first argument and is called
+    // through a forwarding lambda of its own name (within its body too)
+    fn emit_lambda(
+        &self,
+        ctx: &mut EmitContextImpl,
+        var: &str,
+        ty_params: &[Expr],
+        params: &[(String, Option<Expr>)],
+        return_type: Option<&Expr>,
+        body: &[Expr],
+    ) {
+        let indent = ctx.indent();
+        let mut params_str = self.emit_params(params);
+        let return_type_str = return_type.map(|ty| self.emit_type(ty));
+
+        if recursive_call(var, body).is_none() {
+            ctx.emit(&format!(
+                "{}auto {} = {}",
+                indent,
+                var,
+                self.emit_lambda_head(ctx, ty_params, &params_str, return_type_str.as_deref())
+            ));
+
+            self.emit_fn_body(ctx, body, return_type);
+            ctx.emit(&format!("{}}};", indent));
+            return;
+        }
+
+        let rec = format!("{}_rec", var);
+        let args_str = std::iter::once(rec.clone())
+            .chain(params.iter().map(|(param, _)| param.clone()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let forward = format!(
+            "auto {} = {} return {}({}); }};",
+            var,
+            self.emit_lambda_head(ctx, ty_params, &params_str, Some("decltype(auto)")),
+            rec,
+            args_str
+        );
+
+        params_str.insert(0, format!("auto& {}", rec));
+        ctx.emit(&format!(
+            "{}auto {} = {}",
+            indent,
+            rec,
+            self.emit_lambda_head(ctx, ty_params, &params_str, return_type_str.as_deref())
+        ));
+
+        ctx.emit(&format!("{}  {}", indent, forward));
+        self.emit_fn_body(ctx, body, return_type);
+        ctx.emit(&format!("{}}};", indent));
+        ctx.emit(&format!("{}{}", indent, forward));
+    }
+
+    // a function value, its body is on lines of its own unless it is a
+    // single statement, #line directives are left to the enclosing one
+    fn emit_lambda_value(
+        &self,
+        ctx: &EmitContextImpl,
+        params: &[(String, Option<Expr>)],
+        body: &[Expr],
+    ) -> String {
+        let head = self.emit_lambda_head(ctx, &[], &self.emit_params(params), None);
+
+        let mut ctx = ctx.clone();
+        ctx.output = String::new();
+        ctx.level = 0;
+        ctx.line_map = None;
+
+        self.emit_fn_body(&mut ctx, body, None);
+
+        match ctx.output.lines().collect::<Vec<_>>()[..] {
+            [] => format!("{}}}", head),
+            [line] => format!("{} {} }}", head, line.trim()),
+            _ => format!("{}\n{}}}", head, ctx.output),
+        }
+    }
+
+    // untyped parameters are `auto`, i.e. an abbreviated template
+    fn emit_params(&self, params: &[(String, Option<Expr>)]) -> Vec<String> {
+        params
+            .iter()
+            .map(|(param, ty)| match ty {
+                Some(ty) => format!("{} {}", self.emit_type(ty), param),
+                None => format!("auto {}", param),
+            })
+            .collect()
+    }
+
+    // up to the opening brace of the body, lambdas at namespace scope have
+    // nothing to capture and cannot have a capture-default
+    fn emit_lambda_head(
+        &self,
+        ctx: &EmitContextImpl,
+        ty_params: &[Expr],
+        params: &[String],
+        return_type: Option<&str>,
+    ) -> String {
+        let (introducer, specifier) = match ctx.capture {
+            _ if ctx.fn_depth == 0 => ("[]", ""),
+            Capture::Reference => ("[&]", ""),
+            Capture::Value => ("[=]", " mutable"),
+        };
+
+        let template_str = if ty_params.is_empty() {
+            String::new()
+        } else {
+            format!("<{}>", self.emit_template_params(ty_params))
+        };
+        let return_str = return_type.map_or(String::new(), |ty| format!(" -> {}", ty));
+
+        format!(
+            "{}{}({}){}{} {{",
+            introducer,
+            template_str,
+            params.join(", "),
+            specifier,
+            return_str
+        )
+    }
+
+    fn emit_value(&self, ctx: &EmitContextImpl, expr: &Expr) -> String {
+        match &expr.kind {
+            ExprKind::Int(n) => n.to_string(),
+            ExprKind::Float(f) => f.to_string(),
+            ExprKind::Bool(b) => {
                 if *b {
                     "true".to_string()
                 } else {
                     "false".to_string()
                 }
             }
-            Expr::Char(c) => format!("'{}'", c),
-            Expr::String(s) => format!("\"{}\"", s),
-            Expr::Var(var_name) => var_name.clone(),
-
-            Expr::Member(exprs) => self.emit_member(exprs),
-
-            Expr::Bin(lhs, op, rhs) => {
-                let lhs_str = self.emit_value(lhs);
-                let rhs_str = self.emit_value(rhs);
+            ExprKind::Char(c) => format!("'{}'", c),
+            ExprKind::String(s) => format!("\"{}\"", s),
+            // the receiver of a method
+            ExprKind::Var(var_name) if var_name == "self" => "(*this)".to_string(),
+            ExprKind::Var(var_name) => var_name.clone(),
+
+            ExprKind::Member(exprs) => self.emit_member(ctx, exprs),
+            ExprKind::Fn(params, body) => self.emit_lambda_value(ctx, params, body),
+
+            ExprKind::Bin(lhs, op, rhs) => {
+                let lhs_str = self.emit_value(ctx, lhs);
+                let rhs_str = self.emit_value(ctx, rhs);
                 let op_str = match op {
                     BinOp::Add => "+",
                     BinOp::Sub => "-",
@@ -500,9 +785,9 @@ impl EmitDefault {
                 format!("({} {} {})", lhs_str, op_str, rhs_str)
             }
 
-            Expr::Compare(lhs, op, rhs) => {
-                let lhs_str = self.emit_value(lhs);
-                let rhs_str = self.emit_value(rhs);
+            ExprKind::Compare(lhs, op, rhs) => {
+                let lhs_str = self.emit_value(ctx, lhs);
+                let rhs_str = self.emit_value(ctx, rhs);
                 let op_str = match op {
                     CompareOp::Gt => ">",
                     CompareOp::Lt => "<",
@@ -517,8 +802,8 @@ impl EmitDefault {
                 format!("({} {} {})", lhs_str, op_str, rhs_str)
             }
 
-            Expr::Unary(expr, op) => {
-                let expr_str = self.emit_value(expr);
+            ExprKind::Unary(expr, op) => {
+                let expr_str = self.emit_value(ctx, expr);
                 let op_str = match op {
                     UnaryOp::Ref => "&",
                     UnaryOp::Deref => "*",
@@ -528,20 +813,24 @@ impl EmitDefault {
                 format!("{}{}", op_str, expr_str)
             }
 
-            Expr::Bit(lhs, op, rhs) => {
+            ExprKind::Bit(lhs, op, rhs) => {
                 if matches!(&op, BitOp::Rotl | BitOp::Rotr) {
-                    let lhs_str = self.emit_value(lhs);
-                    let rhs_str = self.emit_value(rhs);
-                    let op_str = match op {
-                        BitOp::Rotl => "__builtin_rotateleft32",
-                        BitOp::Rotr => "__builtin_rotateright32",
+                    let lhs_str = self.emit_value(ctx, lhs);
+                    let rhs_str = self.emit_value(ctx, rhs);
+                    // the 32-bit rotate builtins are clang-only, g++ gets
+                    // the C++20 <bit> rotates on the same 32-bit operand
+                    let op_str = match (&self.ctx.cxx_flavor, op) {
+                        (CxxFlavor::Clang, BitOp::Rotl) => "__builtin_rotateleft32",
+                        (CxxFlavor::Clang, BitOp::Rotr) => "__builtin_rotateright32",
+                        (CxxFlavor::Gcc, BitOp::Rotl) => "std::rotl<uint32_t>",
+                        (CxxFlavor::Gcc, BitOp::Rotr) => "std::rotr<uint32_t>",
                         _ => "",
                     };
 
                     format!("{}({}, {})", op_str, lhs_str, rhs_str)
                 } else {
-                    let lhs_str = self.emit_value(lhs);
-                    let rhs_str = self.emit_value(rhs);
+                    let lhs_str = self.emit_value(ctx, lhs);
+                    let rhs_str = self.emit_value(ctx, rhs);
                     let op_str = match op {
                         BitOp::Shl => "<<",
                         BitOp::Shr => ">>",
@@ -555,38 +844,73 @@ impl EmitDefault {
                 }
             }
 
-            Expr::Range(lhs, rhs) => {
-                let lhs_str = self.emit_value(lhs);
-                let rhs_str = self.emit_value(rhs);
+            ExprKind::Range(lhs, rhs) => {
+                let lhs_str = self.emit_value(ctx, lhs);
+                let rhs_str = self.emit_value(ctx, rhs);
 
                 format!("views::iota({}, {})", lhs_str, rhs_str)
             }
 
             // RHS can assume the LHS casts the C++ initializer lists
             // or ideally use vector<T>() as an extended definition
-            Expr::List(exprs) => {
+            ExprKind::List(exprs) => {
                 let elements = exprs
                     .iter()
-                    .map(|e| self.emit_value(e))
+                    .map(|e| self.emit_value(ctx, e))
                     .collect::<Vec<_>>()
                     .join(", ");
 
                 format!("{{{}}}", elements)
             }
 
-            Expr::Call(callee, args) => {
-                let callee_str = self.emit_value(callee);
+            ExprKind::Record(entries) => self.emit_record(ctx, entries, None),
+
+            ExprKind::Construct(name, fields) => match &fields.kind {
+                ExprKind::Record(entries) => format!(
+                    "{}{}",
+                    name,
+                    self.emit_record(
+                        ctx,
+                        entries,
+                        Some(&ExprKind::TypedSymbol(name.clone()).into())
+                    )
+                ),
+                _ => String::new(),
+            },
+
+            // an `if` of single values is a conditional, anything else is
+            // an immediately invoked lambda returning from its branches
+            ExprKind::If(cond, then_body, Some(else_body))
+                if is_single_value(then_body) && is_single_value(else_body) =>
+            {
+                format!(
+                    "({} ? {} : {})",
+                    self.emit_value(ctx, cond),
+                    self.emit_value(ctx, &then_body[0]),
+                    self.emit_value(ctx, &else_body[0])
+                )
+            }
+
+            ExprKind::If(_, _, _) | ExprKind::Match(_, _) => {
+                format!(
+                    "{}()",
+                    self.emit_lambda_value(ctx, &[], std::slice::from_ref(expr))
+                )
+            }
+
+            ExprKind::Call(callee, args) => {
+                let callee_str = self.emit_value(ctx, callee);
                 let args_str = args
                     .iter()
-                    .map(|arg| self.emit_value(arg))
+                    .map(|arg| self.emit_value(ctx, arg))
                     .collect::<Vec<_>>()
                     .join(", ");
 
-                return (format!("{}({})", callee_str, args_str));
+                format!("{}({})", callee_str, args_str)
             }
 
-            Expr::TypedCall(callee, generics, args) => {
-                let callee_str = self.emit_value(callee);
+            ExprKind::TypedCall(callee, generics, args) => {
+                let callee_str = self.emit_value(ctx, callee);
 
                 let generics_str = if !generics.is_empty() {
                     let generics_ty_str = generics
@@ -601,15 +925,15 @@ impl EmitDefault {
 
                 let args_str = args
                     .iter()
-                    .map(|arg| self.emit_value(arg))
+                    .map(|arg| self.emit_value(ctx, arg))
                     .collect::<Vec<_>>()
                     .join(", ");
 
-                return format!("{}{}({})", callee_str, generics_str, args_str);
+                format!("{}{}({})", callee_str, generics_str, args_str)
             }
 
-            Expr::Directive(directive, expr) => {
-                if let Expr::Var(directive_name) = directive.as_ref() {
+            ExprKind::Directive(directive, expr) => {
+                if let ExprKind::Var(directive_name) = &directive.kind {
                     let mut specifier = String::new();
 
                     match directive_name.as_str() {
@@ -620,10 +944,10 @@ impl EmitDefault {
                     }
 
                     if !specifier.is_empty() {
-                        if matches!(**expr, Expr::Directive(_, _)) {
-                            return format!("{}", specifier);
+                        if matches!(expr.kind, ExprKind::Directive(_, _)) {
+                            return specifier;
                         } else {
-                            let value = self.emit_value(expr);
+                            let value = self.emit_value(ctx, expr);
                             return format!("{} {}", specifier, value);
                         }
                     }
@@ -636,24 +960,177 @@ impl EmitDefault {
         }
     }
 
-    fn emit_member(&self, exprs: &[Expr]) -> String {
+    // a value initializing a variable or return value of the type
+    fn emit_value_as(&self, ctx: &EmitContextImpl, expr: &Expr, ty: Option<&Expr>) -> String {
+        match &expr.kind {
+            ExprKind::Record(entries) => self.emit_record(ctx, entries, ty),
+            _ => self.emit_value(ctx, expr),
+        }
+    }
+
+    // records lower to an unordered_map of their key and value types
+    // unless the declared type says otherwise, map types take the entries
+    // as pairs (names as string keys), structs their fields and any other
+    // type is taken to be an aggregate of the named fields
+    fn emit_record(
+        &self,
+        ctx: &EmitContextImpl,
+        entries: &[Vec<Expr>],
+        ty: Option<&Expr>,
+    ) -> String {
+        let entries = record_entries(entries);
+
+        match ty.map(|ty| &ty.kind) {
+            Some(ExprKind::TypedSymbolGeneric(name, args)) if is_map(name) => {
+                let pairs = entries
+                    .iter()
+                    .map(|(key, value)| {
+                        format!(
+                            "{{{}, {}}}",
+                            self.emit_record_key(ctx, key),
+                            self.emit_value_as(ctx, value, args.get(1))
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                format!("{{{}}}", pairs)
+            }
+
+            // positional, the fields between those given are value initialized
+            Some(ExprKind::TypedSymbol(name) | ExprKind::TypedSymbolGeneric(name, _))
+                if self.ctx.structs.contains_key(name) =>
+            {
+                let (ty_params, fields) = &self.ctx.structs[name];
+                let ty_args = match ty.map(|ty| &ty.kind) {
+                    Some(ExprKind::TypedSymbolGeneric(_, args)) => &args[..],
+                    _ => &[],
+                };
+                let given = fields
+                    .iter()
+                    .rposition(|(field, _)| {
+                        entries
+                            .iter()
+                            .any(|(key, _)| matches!(&key.kind, ExprKind::Var(key) if key == field))
+                    })
+                    .map_or(0, |last| last + 1);
+
+                let args = fields[..given]
+                    .iter()
+                    .map(|(field, ty)| {
+                        let value = entries.iter().find(
+                            |(key, _)| matches!(&key.kind, ExprKind::Var(key) if key == field),
+                        );
+
+                        match value {
+                            Some((_, value)) => self.emit_value_as(
+                                ctx,
+                                value,
+                                Some(type_argument(ty, ty_params, ty_args)),
+                            ),
+                            None => "{}".to_string(),
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                format!("{{{}}}", args)
+            }
+
+            Some(_) => {
+                let fields = entries
+                    .iter()
+                    .map(|(key, value)| {
+                        format!(
+                            ".{} = {}",
+                            self.emit_value(ctx, key),
+                            self.emit_value(ctx, value)
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                format!("{{{}}}", fields)
+            }
+
+            None if entries.is_empty() => "{}".to_string(),
+
+            None => {
+                let pairs = entries
+                    .iter()
+                    .map(|(key, value)| {
+                        format!(
+                            "{{{}, {}}}",
+                            self.emit_record_key(ctx, key),
+                            self.emit_value(ctx, value)
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                format!("{}{{{}}}", self.emit_record_type(ctx, &entries), pairs)
+            }
+        }
+    }
+
+    fn emit_record_key(&self, ctx: &EmitContextImpl, key: &Expr) -> String {
+        match &key.kind {
+            ExprKind::Var(name) => format!("\"{}\"", name),
+            _ => self.emit_value(ctx, key),
+        }
+    }
+
+    // the map type of an untyped record, values of different literal types
+    // are taken to be doubles and those of no literal type are declared
+    // by the first value
+    fn emit_record_type(&self, ctx: &EmitContextImpl, entries: &[(&Expr, &Expr)]) -> String {
+        let key_type = match entries.first().map(|(key, _)| &key.kind) {
+            Some(ExprKind::Int(_)) => "int",
+            _ => "string",
+        };
+
+        let value_types = entries
+            .iter()
+            .map(|(_, value)| match &value.kind {
+                ExprKind::Int(_) => Some("int".to_string()),
+                ExprKind::Float(_) => Some("double".to_string()),
+                ExprKind::Bool(_) => Some("bool".to_string()),
+                ExprKind::Char(_) => Some("char".to_string()),
+                ExprKind::String(_) => Some("string".to_string()),
+                ExprKind::Record(entries) if !entries.is_empty() => {
+                    Some(self.emit_record_type(ctx, &record_entries(entries)))
+                }
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>();
+
+        let value_type = match value_types {
+            Some(types) if types.iter().all(|ty| *ty == types[0]) => types[0].clone(),
+            Some(_) => "double".to_string(),
+            None => format!("decltype({})", self.emit_value(ctx, entries[0].1)),
+        };
+
+        format!("unordered_map<{}, {}>", key_type, value_type)
+    }
+
+    fn emit_member(&self, ctx: &EmitContextImpl, exprs: &[Expr]) -> String {
         exprs
             .iter()
-            .map(|e| self.emit_value(e))
+            .map(|e| self.emit_value(ctx, e))
             .collect::<Vec<_>>()
             .join(".")
     }
 
-    fn emit_type(&self, ty: &Expr) -> String {
-        match ty {
-            Expr::TypedSymbol(type_name) => type_name.clone(),
-            Expr::TypedVariant(lhs, rhs) => self.emit_variant(lhs, rhs),
+    pub fn emit_type(&self, ty: &Expr) -> String {
+        match &ty.kind {
+            ExprKind::TypedSymbol(type_name) => type_name.clone(),
+            ExprKind::TypedVariant(lhs, rhs) => self.emit_variant(lhs, rhs),
 
-            Expr::TypedUnary(expr) => {
-                if let Expr::Unary(ty_expr, op) = &**expr {
+            ExprKind::TypedUnary(expr) => {
+                if let ExprKind::Unary(ty_expr, op) = &expr.kind {
                     return format!(
                         "{}{}",
-                        self.emit_type(&*ty_expr),
+                        self.emit_type(ty_expr),
                         match op {
                             UnaryOp::Ref => "&",
                             UnaryOp::Deref => "*",
@@ -662,20 +1139,22 @@ impl EmitDefault {
                     );
                 }
 
-                format!("")
+                String::new()
             }
 
-            Expr::TypedLiteral(type_name) => {
-                format!("decltype({})", self.emit_value(type_name))
+            ExprKind::TypedLiteral(type_name) => {
+                format!("decltype({})", self.emit_value(&self.ctx, type_name))
             }
 
-            Expr::TypedOptional(ty) => {
+            ExprKind::TypedOptional(ty) => {
                 format!("optional<{}>", self.emit_type(ty))
             }
 
-            Expr::TypedMember(member_expr) => self.emit_value(member_expr).replace(".", "::"),
+            ExprKind::TypedMember(member_expr) => {
+                self.emit_value(&self.ctx, member_expr).replace(".", "::")
+            }
 
-            Expr::TypedSymbolGeneric(type_name, ty_params) => {
+            ExprKind::TypedSymbolGeneric(type_name, ty_params) => {
                 let ty_params_str = if !ty_params.is_empty() {
                     let ty_params_str = ty_params
                         .iter()
@@ -690,8 +1169,28 @@ impl EmitDefault {
                 format!("{}{}", type_name, ty_params_str)
             }
 
-            Expr::TypedFn(fn_expr) => {
-                if let Expr::Fn(params, body) = fn_expr.as_ref() {
+            // an anonymous aggregate of the fields
+            ExprKind::TypedRecord(record_expr) => match &record_expr.kind {
+                ExprKind::Record(entries) => {
+                    let fields = entries
+                        .iter()
+                        .filter_map(|entry| match &entry[..] {
+                            [key, ty] => Some(format!(
+                                "{} {}; ",
+                                self.emit_type(ty),
+                                self.emit_value(&self.ctx, key)
+                            )),
+                            _ => None,
+                        })
+                        .collect::<String>();
+
+                    format!("struct {{ {}}}", fields)
+                }
+                _ => String::new(),
+            },
+
+            ExprKind::TypedFn(fn_expr) => {
+                if let ExprKind::Fn(params, body) = &fn_expr.kind {
                     if let Some(last_expr) = body.last() {
                         let params_str = params
                             .iter()
@@ -719,10 +1218,10 @@ impl EmitDefault {
     fn emit_variant(&self, lhs: &Expr, rhs: &Expr) -> String {
         let lhs_str = self.emit_type(lhs);
 
-        match rhs {
+        match &rhs.kind {
             // match against RHS typed variants which is the nesting form
             // based on the parsing rule, expecting a LHS and RHS
-            Expr::TypedVariant(next_lhs, next_rhs) => {
+            ExprKind::TypedVariant(next_lhs, next_rhs) => {
                 let rhs_str = self.emit_variant(next_lhs, next_rhs);
                 format!("{} | {}", lhs_str, rhs_str)
             }
@@ -734,29 +1233,33 @@ impl EmitDefault {
     }
 
     fn emit_extend(&self, ctx: &mut EmitContextImpl, ident: &str, ext_ty: &str, expr: &Expr) {
-        match expr {
-            Expr::TypeAlias(name, ty_params, rhs) => {
+        match &expr.kind {
+            ExprKind::TypeAlias(name, ty_params, rhs) => {
                 // we'll only modify the RHS type (not the generic type params) based on @extend
                 // let updated_rhs = self.replace_with_enable_if(ident, ext_ty, rhs);
-                let updated_rhs = match rhs.as_ref() {
-                    Expr::TypedSymbol(type_name) if type_name == ident => {
-                        Box::new(Expr::TypedSymbol(format!(
+                let updated_rhs = match &rhs.kind {
+                    ExprKind::TypedSymbol(type_name) if type_name == ident => Box::new(Expr::new(
+                        ExprKind::TypedSymbol(format!(
                             "std::enable_if_t<std::is_same<{}, {}>::value, {}>",
                             ident, ext_ty, ident
-                        )))
-                    }
+                        )),
+                        rhs.span,
+                    )),
 
                     _ => rhs.clone(),
                 };
 
                 self.emit_expr(
                     ctx,
-                    &Expr::TypeAlias(name.clone(), ty_params.clone(), updated_rhs),
+                    &Expr::new(
+                        ExprKind::TypeAlias(name.clone(), ty_params.clone(), updated_rhs),
+                        expr.span,
+                    ),
                 );
             }
 
             // recurse all nested extend directives
-            Expr::Directive(directive, inner_expr) => {
+            ExprKind::Directive(_, inner_expr) => {
                 self.emit_extend(ctx, ident, ext_ty, inner_expr);
                 // self.emit_expr(ctx, expr);
             }
@@ -765,3 +1268,128 @@ impl EmitDefault {
         }
     }
 }
+
+// functions return nothing when no branch of their body ends in a value
+fn deduced_return_type(body: &[Expr]) -> &'static str {
+    if returns_value(body) {
+        "auto"
+    } else {
+        "void"
+    }
+}
+
+fn returns_value(body: &[Expr]) -> bool {
+    let Some(last) = body.last() else {
+        return false;
+    };
+
+    match &last.kind {
+        ExprKind::If(_, then_body, else_body) => {
+            returns_value(then_body) || else_body.as_deref().is_some_and(returns_value)
+        }
+
+        ExprKind::Match(_, cases) => cases.iter().any(|(_, body)| returns_value(body)),
+        ExprKind::Directive(_, inner) => returns_value(std::slice::from_ref(inner)),
+        _ => last.is_value(),
+    }
+}
+
+fn is_single_value(body: &[Expr]) -> bool {
+    matches!(body, [expr] if expr.is_value() && !matches!(expr.kind, ExprKind::If(_, _, _) | ExprKind::Match(_, _)))
+}
+
+fn record_entries(entries: &[Vec<Expr>]) -> Vec<(&Expr, &Expr)> {
+    entries
+        .iter()
+        .filter_map(|entry| match &entry[..] {
+            [key, value] => Some((key, value)),
+            _ => None,
+        })
+        .collect()
+}
+
+// the fields of a record alias (`type P = { x: int } end`) by name
+fn record_alias_fields(rhs: &Expr) -> Option<Vec<(String, Expr)>> {
+    let ExprKind::TypedRecord(record) = &rhs.kind else {
+        return None;
+    };
+    let ExprKind::Record(entries) = &record.kind else {
+        return None;
+    };
+
+    let fields = entries
+        .iter()
+        .filter_map(|entry| match &entry[..] {
+            [key, ty] => match &key.kind {
+                ExprKind::Var(field) => Some((field.clone(), ty.clone())),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+
+    Some(fields)
+}
+
+// the argument given for a type parameter, any other type as is
+fn type_argument<'a>(ty: &'a Expr, ty_params: &[Expr], ty_args: &'a [Expr]) -> &'a Expr {
+    let ExprKind::TypedSymbol(name) = &ty.kind else {
+        return ty;
+    };
+
+    ty_params
+        .iter()
+        .position(|param| matches!(&param.kind, ExprKind::TypedSymbol(param) if param == name))
+        .and_then(|index| ty_args.get(index))
+        .unwrap_or(ty)
+}
+
+fn is_map(name: &str) -> bool {
+    matches!(name, "map" | "unordered_map")
+}
+
+// the first reference of a function body to the function's own name
+pub fn recursive_call(name: &str, body: &[Expr]) -> Option<Span> {
+    let mut call = None;
+
+    for expr in body {
+        expr.visit(&mut EmitContextImpl::new(), &mut |_, expr| {
+            if matches!(&expr.kind, ExprKind::Var(var) if var == name) && call.is_none() {
+                call = Some(expr.span);
+            }
+        });
+    }
+
+    call
+}
+
+// lines of multi-line code after the first one (e.g. the body of a lambda
+// value) are indented as the first one
+fn indent_continuation(code: &str) -> String {
+    let lines = code.trim_start_matches('\n');
+    let leading = &code[..code.len() - lines.len()];
+
+    let Some((first, rest)) = lines.split_once('\n') else {
+        return code.into();
+    };
+
+    let indent = &first[..first.len() - first.trim_start().len()];
+
+    if indent.is_empty() {
+        return code.into();
+    }
+
+    let rest = rest
+        .split('\n')
+        .map(|line| {
+            if line.is_empty() {
+                String::new()
+            } else {
+                format!("{}{}", indent, line)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!("{}{}\n{}", leading, first, rest)
+}
@@ -0,0 +1,58 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+// errors flowing out of compile(), each kind maps to its own exit status
+// so build tools can tell a broken source apart from a broken toolchain
+#[derive(Debug)]
+pub enum CompileError {
+    // reading the input or writing the output failed
+    Io(PathBuf, io::Error),
+    // rendered parse diagnostics
+    Parse(String),
+    // rendered diagnostics from passes over the parsed program
+    Semantic(String),
+    // the C++ compiler could not be run or did not succeed
+    Backend(String),
+    // rendered diagnostics of an error stopping the interpreter
+    Runtime(String),
+    // `esper fmt --check` found inputs that are not formatted
+    Unformatted(Vec<PathBuf>),
+}
+
+impl CompileError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CompileError::Io(_, _) => 1,
+            CompileError::Parse(_) => 2,
+            CompileError::Semantic(_) => 3,
+            CompileError::Backend(_) => 4,
+            CompileError::Runtime(_) => 5,
+            CompileError::Unformatted(_) => 6,
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::Io(path, err) => write!(f, "error: {}: {}", path.display(), err),
+            CompileError::Parse(msg) | CompileError::Semantic(msg) | CompileError::Runtime(msg) => {
+                write!(f, "{}", msg)
+            }
+            CompileError::Backend(msg) => write!(f, "error: {}", msg),
+
+            CompileError::Unformatted(paths) => {
+                write!(f, "error: {} file(s) would be reformatted", paths.len())?;
+
+                for path in paths {
+                    write!(f, "\n  {}", path.display())?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
@@ -0,0 +1,127 @@
+use crate::check::Pass;
+use crate::diagnostics::{Diagnostic, NON_EXHAUSTIVE_MATCH, UNREACHABLE_ARM};
+use crate::parser::{Expr, ExprKind};
+use crate::span::{LineIndex, Span};
+use crate::types::{match_types, Type};
+use crate::visit::{EsperContext, Visitor};
+
+// a `match` is emitted as one `if constexpr` per arm within `std::visit`,
+// a value no arm matches is silently ignored, when the matched value is a
+// variant of known alternatives every alternative must have an arm and
+// every arm must match one of them exactly (`float` never matches a
+// `double`)
+pub struct Exhaustiveness;
+
+struct ExhaustivenessContext;
+
+impl EsperContext for ExhaustivenessContext {
+    fn new() -> Self {
+        ExhaustivenessContext
+    }
+}
+
+impl Pass for Exhaustiveness {
+    fn run(&self, program: &Expr, source: &str) -> Vec<Diagnostic> {
+        let types = match_types(program);
+        let lines = LineIndex::new(source);
+        let mut diagnostics = vec![];
+
+        program.visit(&mut ExhaustivenessContext, &mut |_, expr| {
+            let ExprKind::Match(cond, cases) = &expr.kind else {
+                return;
+            };
+
+            let Some((scrutinee, arms)) = types.get(&expr.span) else {
+                return;
+            };
+
+            let alternatives = match scrutinee {
+                Type::Variant(alternatives) => alternatives,
+                Type::Ref(inner) => match inner.as_ref() {
+                    Type::Variant(alternatives) => alternatives,
+                    _ => return,
+                },
+                _ => return,
+            };
+
+            // alternatives the checker cannot tell may be matched by any arm
+            let known = alternatives.iter().all(Type::is_known);
+            let mut previous_end = cond.span.end;
+            let mut matched: Vec<(&Type, Span)> = vec![];
+
+            for ((pattern, body), arm) in cases.iter().zip(arms) {
+                let span = pattern_span(source, previous_end, pattern, body);
+                previous_end = body.last().map_or(span.end, |last| last.span.end);
+
+                if let Some((_, first)) = matched.iter().find(|(ty, _)| *ty == arm) {
+                    diagnostics.push(
+                        Diagnostic::warning(format!("unreachable arm for `{}`", pattern), span)
+                            .with_code(UNREACHABLE_ARM)
+                            .with_label("duplicate arm")
+                            .with_note(format!(
+                                "`{}` is already matched on line {}",
+                                pattern,
+                                lines.line(first.start)
+                            )),
+                    );
+                } else if known && arm.is_known() && !alternatives.contains(arm) {
+                    diagnostics.push(
+                        Diagnostic::warning(format!("unreachable arm for `{}`", pattern), span)
+                            .with_code(UNREACHABLE_ARM)
+                            .with_label("this arm never matches")
+                            .with_note(format!(
+                                "`{}` is not an alternative of `{}`",
+                                arm,
+                                Type::Variant(alternatives.clone())
+                            )),
+                    );
+                }
+
+                matched.push((arm, span));
+            }
+
+            // an arm of a type the checker cannot tell may match anything
+            if !known || arms.iter().any(|arm| !arm.is_known()) {
+                return;
+            }
+
+            let missing = alternatives
+                .iter()
+                .filter(|alternative| !arms.contains(alternative))
+                .map(|alternative| format!("`{}`", alternative))
+                .collect::<Vec<_>>();
+
+            if !missing.is_empty() {
+                diagnostics.push(
+                    Diagnostic::error(
+                        format!("non-exhaustive match: {} not covered", missing.join(", ")),
+                        cond.span,
+                    )
+                    .with_code(NON_EXHAUSTIVE_MATCH)
+                    .with_label(format!(
+                        "matched value of type `{}`",
+                        Type::Variant(alternatives.clone())
+                    ))
+                    .with_note(format!(
+                        "add an arm for every missing alternative, e.g. `| {} -> ...`",
+                        missing[0].trim_matches('`')
+                    )),
+                );
+            }
+        });
+
+        diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+        diagnostics
+    }
+}
+
+// arms hold their pattern as a name, it is the last occurrence of the
+// name between the previous arm and the body
+fn pattern_span(source: &str, from: usize, pattern: &str, body: &[Expr]) -> Span {
+    let to = body.first().map_or(source.len(), |first| first.span.start);
+
+    match source.get(from..to).and_then(|text| text.rfind(pattern)) {
+        Some(offset) => Span::new(from + offset, from + offset + pattern.len()),
+        None => Span::new(from, to),
+    }
+}
@@ -0,0 +1,646 @@
+use crate::diagnostics::Diagnostics;
+use crate::parser::{parse_comments, BinOp, BitOp, Comment, CompareOp, Expr, ExprKind, UnaryOp};
+use crate::span::Span;
+
+const INDENT: &str = "  ";
+
+// constructs with a single statement in their body are kept on one line
+// when they fit in this width, e.g. `let add = |a, b| a + b end`
+const MAX_INLINE_WIDTH: usize = 60;
+
+// binding power of expressions as operands, see the precedence levels
+// in the operators() rule, lets and assignments take the rest of the input
+const GREEDY: u8 = 0;
+const UNARY: u8 = 11;
+const PRIMARY: u8 = 12;
+
+// prints a module back in the canonical style, bodies are indented by two
+// spaces, single blank lines between statements are kept and comments stay
+// in front of (or on the same line as) the statement they were found at
+pub fn format_source(source: &str) -> Result<String, Diagnostics> {
+    let program = crate::parse(source)?;
+    let mut printer = Printer {
+        source,
+        comments: parse_comments(source),
+        next_comment: 0,
+    };
+
+    let mut out = printer.expr(&program.root, 0);
+    out.push('\n');
+
+    Ok(out)
+}
+
+struct Printer<'a> {
+    source: &'a str,
+    comments: Vec<Comment>,
+    next_comment: usize,
+}
+
+impl Printer<'_> {
+    fn pending_comment(&self, before: usize) -> Option<&Comment> {
+        self.comments
+            .get(self.next_comment)
+            .filter(|comment| comment.span.start < before)
+    }
+
+    fn take_comment(&mut self) -> Comment {
+        self.next_comment += 1;
+        self.comments[self.next_comment - 1].clone()
+    }
+
+    // comments left inside a statement or right after it on the same line
+    fn trailing_comment(&self, end: usize) -> bool {
+        self.comments.get(self.next_comment).is_some_and(|comment| {
+            comment.span.start < end || !self.source[end..comment.span.start].contains('\n')
+        })
+    }
+
+    // offset of a keyword or punctuation between two nodes, e.g. the `else`
+    // closing the then branch, skipping over comments
+    fn find_token(&self, from: usize, to: usize, token: &str) -> Option<usize> {
+        let gap = self.source.get(from..to)?;
+
+        gap.match_indices(token)
+            .map(|(offset, _)| from + offset)
+            .find(|offset| {
+                !self
+                    .comments
+                    .iter()
+                    .any(|comment| comment.span.contains(*offset))
+            })
+    }
+
+    fn has_comments(&self, span: Span) -> bool {
+        self.pending_comment(span.end).is_some()
+    }
+
+    // a single blank line is kept where the source had at least one
+    fn line_break(&self, out: &mut String, prev_end: Option<usize>, next_start: usize) {
+        if out.is_empty() {
+            return;
+        }
+
+        out.push('\n');
+
+        if let Some(prev_end) = prev_end {
+            let gap = self.source.get(prev_end..next_start).unwrap_or("");
+            let lines = gap.split('\n').collect::<Vec<_>>();
+
+            if lines.len() > 2
+                && lines[1..lines.len() - 1]
+                    .iter()
+                    .any(|line| line.trim().is_empty())
+            {
+                out.push('\n');
+            }
+        }
+    }
+
+    // statements of a program, a body or entries of a struct, each on its
+    // own line, the prefixes are printed in front of the items (e.g. the
+    // names of struct entries)
+    fn statements(
+        &mut self,
+        items: &[&Expr],
+        prefixes: &[String],
+        indent: usize,
+        separator: &str,
+        end: usize,
+    ) -> String {
+        let pad = INDENT.repeat(indent);
+        let mut out = String::new();
+        let mut prev_end = None;
+
+        for (i, item) in items.iter().enumerate() {
+            while self.pending_comment(item.span.start).is_some() {
+                let comment = self.take_comment();
+
+                self.line_break(&mut out, prev_end, comment.span.start);
+                out.push_str(&pad);
+                out.push_str(&comment.text);
+                prev_end = Some(comment.span.end);
+            }
+
+            self.line_break(&mut out, prev_end, item.span.start);
+
+            let mut text = self.expr(item, indent);
+
+            // without separators a leading `-`, `*` or `&` would continue
+            // the previous statement as a binary operator
+            if separator.is_empty() && i > 0 && text.starts_with(['-', '*', '&']) {
+                text = parens(&text);
+            }
+
+            out.push_str(&pad);
+            out.push_str(prefixes.get(i).map_or("", |prefix| prefix));
+            out.push_str(&text);
+
+            if i + 1 < items.len() {
+                out.push_str(separator);
+            }
+
+            prev_end = Some(item.span.end);
+
+            while self.trailing_comment(item.span.end) {
+                let comment = self.take_comment();
+
+                out.push(' ');
+                out.push_str(&comment.text);
+                prev_end = Some(prev_end.unwrap_or(0).max(comment.span.end));
+            }
+        }
+
+        while self.pending_comment(end).is_some() {
+            let comment = self.take_comment();
+
+            self.line_break(&mut out, prev_end, comment.span.start);
+            out.push_str(&pad);
+            out.push_str(&comment.text);
+            prev_end = Some(comment.span.end);
+        }
+
+        out
+    }
+
+    // `header body end`, on a single line when the body is a single short
+    // statement without comments
+    fn block(&mut self, header: &str, body: &[Expr], indent: usize, span: Span) -> String {
+        if let Some(inline) = self.inline_body(body, indent, span) {
+            return format!("{} {} end", header, inline);
+        }
+
+        let body = self.body(body, indent, span.end);
+        format!("{}{}\n{}end", header, body, INDENT.repeat(indent))
+    }
+
+    fn inline_body(&mut self, body: &[Expr], indent: usize, span: Span) -> Option<String> {
+        if body.len() != 1 || self.has_comments(span) {
+            return None;
+        }
+
+        let inline = self.expr(&body[0], indent);
+
+        (!inline.contains('\n') && inline.len() <= MAX_INLINE_WIDTH).then_some(inline)
+    }
+
+    // statements on the lines below a header, indented one level deeper
+    fn body(&mut self, body: &[Expr], indent: usize, end: usize) -> String {
+        let items = body.iter().collect::<Vec<_>>();
+        let statements = self.statements(&items, &[], indent + 1, ";", end);
+
+        if statements.is_empty() {
+            statements
+        } else {
+            format!("\n{}", statements)
+        }
+    }
+
+    fn list(&mut self, exprs: &[Expr], indent: usize) -> String {
+        exprs
+            .iter()
+            .map(|expr| self.expr(expr, indent))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn operand(&mut self, expr: &Expr, min_precedence: u8, indent: usize) -> String {
+        let text = self.expr(expr, indent);
+
+        if precedence(expr) < min_precedence {
+            parens(&text)
+        } else {
+            text
+        }
+    }
+
+    // positions taking a primary() in the grammar, anything but operators
+    fn primary(&mut self, expr: &Expr, indent: usize) -> String {
+        let text = self.expr(expr, indent);
+
+        match &expr.kind {
+            ExprKind::Bin(_, _, _)
+            | ExprKind::Compare(_, _, _)
+            | ExprKind::Bit(_, _, _)
+            | ExprKind::Unary(_, _) => parens(&text),
+            _ => text,
+        }
+    }
+
+    // operators are left-associative, an operand on the right binding as
+    // loosely as the operator is put in parens
+    fn binary(&mut self, expr: &Expr, lhs: &Expr, op: &str, rhs: &Expr, indent: usize) -> String {
+        let level = precedence(expr);
+        let lhs = self.operand(lhs, level, indent);
+        let rhs = self.operand(rhs, level + 1, indent);
+
+        format!("{} {} {}", lhs, op, rhs)
+    }
+
+    fn params(&mut self, params: &[(String, Option<Expr>)]) -> String {
+        params
+            .iter()
+            .map(|(param, ty)| match ty {
+                Some(ty) => format!("{}: {}", param, self.ty(ty)),
+                None => param.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn expr(&mut self, expr: &Expr, indent: usize) -> String {
+        let pad = INDENT.repeat(indent);
+
+        match &expr.kind {
+            ExprKind::Program(items) => {
+                let items = items.iter().map(|item| &**item).collect::<Vec<_>>();
+                self.statements(&items, &[], indent, "", self.source.len())
+            }
+
+            ExprKind::Let(name, ty_params, value) => {
+                let ty_params = self.ty_params(ty_params);
+                format!("let {}{} = {}", name, ty_params, self.expr(value, indent))
+            }
+
+            ExprKind::TypedLet(name, ty_params, ty, value) => {
+                let ty_params = self.ty_params(ty_params);
+                let ty = self.ty(ty);
+                format!(
+                    "let {}{}: {} = {}",
+                    name,
+                    ty_params,
+                    ty,
+                    self.expr(value, indent)
+                )
+            }
+
+            ExprKind::Assign(lhs, rhs) => {
+                let lhs = self.expr(lhs, indent);
+                format!("{} = {}", lhs, self.expr(rhs, indent))
+            }
+
+            ExprKind::Var(name) => name.clone(),
+            ExprKind::Int(value) => value.to_string(),
+            // as written, debug formatting would give `1e-7` for `0.0000001`
+            ExprKind::Float(value) => match self.source.get(expr.span.start..expr.span.end) {
+                Some(text) if text.parse::<f64>() == Ok(*value) => text.into(),
+                _ => match value.to_string() {
+                    text if text.contains('.') => text,
+                    text => format!("{}.0", text),
+                },
+            },
+            ExprKind::Bool(value) => value.to_string(),
+            ExprKind::Char(value) => format!("'{}'", value),
+            ExprKind::String(value) => format!("\"{}\"", value),
+            ExprKind::Pass => "pass".into(),
+
+            ExprKind::List(exprs) => format!("[{}]", self.list(exprs, indent)),
+
+            ExprKind::Record(entries) if entries.is_empty() => "{}".into(),
+
+            ExprKind::Record(entries) => {
+                let entries = entries
+                    .iter()
+                    .map(|entry| {
+                        format!(
+                            "{}: {}",
+                            self.expr(&entry[0], indent),
+                            self.expr(&entry[1], indent)
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                format!("{{ {} }}", entries)
+            }
+
+            ExprKind::Construct(name, fields) => {
+                format!("{} {}", name, self.expr(fields, indent))
+            }
+
+            ExprKind::Range(start, end) => {
+                let start = self.expr(start, indent);
+                format!("{}..{}", start, self.expr(end, indent))
+            }
+
+            ExprKind::Directive(directive, expr) => {
+                let directive = self.expr(directive, indent);
+                format!("@{} {}", directive, self.primary(expr, indent))
+            }
+
+            ExprKind::Unary(operand, op) => {
+                let op = match op {
+                    UnaryOp::Ref => "&",
+                    UnaryOp::Deref => "*",
+                    UnaryOp::BitNot => "~",
+                };
+
+                format!("{}{}", op, self.operand(operand, UNARY, indent))
+            }
+
+            ExprKind::Bin(lhs, op, rhs) => {
+                let op = match op {
+                    BinOp::Add => "+",
+                    BinOp::Sub => "-",
+                    BinOp::Mul => "*",
+                    BinOp::Div => "/",
+                };
+
+                self.binary(expr, lhs, op, rhs, indent)
+            }
+
+            ExprKind::Compare(lhs, op, rhs) => {
+                let op = match op {
+                    CompareOp::Gt => "gt",
+                    CompareOp::Lt => "lt",
+                    CompareOp::Gte => "gte",
+                    CompareOp::Lte => "lte",
+                    CompareOp::Eq => "eq",
+                    CompareOp::Neq => "neq",
+                    CompareOp::And => "and",
+                    CompareOp::Or => "or",
+                };
+
+                self.binary(expr, lhs, op, rhs, indent)
+            }
+
+            ExprKind::Bit(lhs, op, rhs) => {
+                let op = match op {
+                    BitOp::Shl => "shl",
+                    BitOp::Shr => "shr",
+                    BitOp::And => "band",
+                    BitOp::Or => "bor",
+                    BitOp::Xor => "xor",
+                    BitOp::Rotl => "rotl",
+                    BitOp::Rotr => "rotr",
+                };
+
+                self.binary(expr, lhs, op, rhs, indent)
+            }
+
+            ExprKind::If(cond, then_body, else_body) => {
+                let header = format!("if {} then", self.expr(cond, indent));
+
+                match else_body {
+                    None => self.block(&header, then_body, indent, expr.span),
+
+                    Some(else_body) => {
+                        let then_end = then_body.last().map_or(cond.span.end, |last| last.span.end);
+                        let else_start = else_body
+                            .first()
+                            .map_or(expr.span.end, |first| first.span.start);
+                        let then_span = Span::new(
+                            expr.span.start,
+                            self.find_token(then_end, else_start, "else")
+                                .unwrap_or(else_start),
+                        );
+                        let else_span = Span::new(then_span.end, expr.span.end);
+
+                        if !self.has_comments(expr.span) {
+                            if let (Some(then_inline), Some(else_inline)) = (
+                                self.inline_body(then_body, indent, then_span),
+                                self.inline_body(else_body, indent, else_span),
+                            ) {
+                                let inline =
+                                    format!("{} {} else {} end", header, then_inline, else_inline);
+
+                                if inline.len() <= MAX_INLINE_WIDTH {
+                                    return inline;
+                                }
+                            }
+                        }
+
+                        let then_body = self.body(then_body, indent, then_span.end);
+                        let else_body = self.body(else_body, indent, else_span.end);
+
+                        format!(
+                            "{}{}\n{}else{}\n{}end",
+                            header, then_body, pad, else_body, pad
+                        )
+                    }
+                }
+            }
+
+            ExprKind::Loop(var, iter, body) => {
+                let var = self.expr(var, indent);
+                let header = format!("for {} in {}", var, self.primary(iter, indent));
+
+                self.block(&header, body, indent, expr.span)
+            }
+
+            ExprKind::Fn(params, body) => {
+                let header = format!("|{}|", self.params(params));
+                self.block(&header, body, indent, expr.span)
+            }
+
+            ExprKind::Match(cond, cases) => {
+                let mut out = format!("match {} with", self.expr(cond, indent));
+
+                for (i, (pattern, body)) in cases.iter().enumerate() {
+                    let next_start = cases
+                        .get(i + 1)
+                        .and_then(|(_, next)| next.first())
+                        .map_or(expr.span.end, |next| next.span.start);
+                    let body_end = body.last().map_or(cond.span.end, |last| last.span.end);
+                    let end = self
+                        .find_token(body_end, next_start, ",")
+                        .unwrap_or(next_start);
+                    let span = Span::new(body.first().map_or(end, |first| first.span.start), end);
+                    let header = format!("| {} ->", pattern);
+
+                    let case = match self.inline_body(body, indent, span) {
+                        Some(inline) => format!("{} {}", header, inline),
+                        None => format!("{}{}", header, self.body(body, indent, end)),
+                    };
+
+                    out.push_str(&format!("\n{}{},", pad, case));
+
+                    while self.trailing_comment(end) {
+                        out.push(' ');
+                        out.push_str(&self.take_comment().text);
+                    }
+                }
+
+                format!("{}\n{}end", out, pad)
+            }
+
+            ExprKind::Struct(name, ty_params, entries)
+                if entries.is_empty() && !self.has_comments(expr.span) =>
+            {
+                format!("struct {}{} end", name, self.ty_params(ty_params))
+            }
+
+            // typed methods are written as typed definitions without `let`
+            ExprKind::Struct(name, ty_params, entries) => {
+                let items = entries
+                    .iter()
+                    .map(|(_, value)| match &value.kind {
+                        ExprKind::TypedLet(_, _, _, function) => function.as_ref(),
+                        _ => value,
+                    })
+                    .collect::<Vec<_>>();
+                let prefixes = entries
+                    .iter()
+                    .map(|(entry, value)| match &value.kind {
+                        ExprKind::TypedLet(_, _, ty, _) => format!("{}: {} = ", entry, self.ty(ty)),
+                        _ => format!("{}: ", entry),
+                    })
+                    .collect::<Vec<_>>();
+                let entries = self.statements(&items, &prefixes, indent + 1, ",", expr.span.end);
+                let ty_params = self.ty_params(ty_params);
+
+                format!("struct {}{}\n{}\n{}end", name, ty_params, entries, pad)
+            }
+
+            ExprKind::TypeAlias(name, ty_params, ty) => {
+                let ty_params = self.ty_params(ty_params);
+
+                format!("type {}{} = {} end", name, ty_params, self.ty(ty))
+            }
+
+            // members are chained without separators after the first dot,
+            // e.g. `p.q.` is [p, [q]] and `esper.print(a)` is [esper, print(a)]
+            ExprKind::Member(exprs) => {
+                let mut out = self.expr(&exprs[0], indent);
+                out.push('.');
+
+                for expr in &exprs[1..] {
+                    out.push_str(&self.expr(expr, indent));
+                }
+
+                out
+            }
+
+            ExprKind::Call(callee, args) => {
+                let callee = self.expr(callee, indent);
+                format!("{}({})", callee, self.list(args, indent))
+            }
+
+            ExprKind::TypedCall(callee, ty_args, args) => {
+                let callee = self.expr(callee, indent);
+                let ty_args = self.types(ty_args);
+                format!("{}<{}>({})", callee, ty_args, self.list(args, indent))
+            }
+
+            ExprKind::TypedSymbol(_)
+            | ExprKind::TypedFn(_)
+            | ExprKind::TypedUnary(_)
+            | ExprKind::TypedLiteral(_)
+            | ExprKind::TypedMember(_)
+            | ExprKind::TypedRecord(_)
+            | ExprKind::TypedOptional(_)
+            | ExprKind::TypedSymbolGeneric(_, _)
+            | ExprKind::TypedVariant(_, _) => self.ty(expr),
+        }
+    }
+
+    fn types(&mut self, types: &[Expr]) -> String {
+        types
+            .iter()
+            .map(|ty| self.ty(ty))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    // `<T, U>` after the name of generic definitions
+    fn ty_params(&mut self, ty_params: &[Expr]) -> String {
+        if ty_params.is_empty() {
+            String::new()
+        } else {
+            format!("<{}>", self.types(ty_params))
+        }
+    }
+
+    fn ty(&mut self, ty: &Expr) -> String {
+        match &ty.kind {
+            ExprKind::TypedSymbol(name) => name.clone(),
+
+            ExprKind::TypedSymbolGeneric(name, ty_params) => {
+                format!("{}<{}>", name, self.types(ty_params))
+            }
+
+            ExprKind::TypedUnary(unary) => match &unary.kind {
+                ExprKind::Unary(inner, UnaryOp::Ref) => format!("&{}", self.ty(inner)),
+                ExprKind::Unary(inner, _) => format!("*{}", self.ty(inner)),
+                _ => self.ty(unary),
+            },
+
+            ExprKind::TypedLiteral(value) | ExprKind::TypedMember(value) => self.expr(value, 0),
+
+            ExprKind::TypedOptional(inner) => format!("?{}", self.ty(inner)),
+
+            ExprKind::TypedRecord(record) => match &record.kind {
+                ExprKind::Record(entries) if !entries.is_empty() => {
+                    let entries = entries
+                        .iter()
+                        .map(|entry| format!("{}: {}", self.expr(&entry[0], 0), self.ty(&entry[1])))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+
+                    format!("{{ {} }}", entries)
+                }
+
+                _ => "{}".into(),
+            },
+
+            ExprKind::TypedFn(fn_expr) => match &fn_expr.kind {
+                ExprKind::Fn(params, body) => {
+                    let params = self.params(params);
+                    let ret = body.last().map(|ret| self.ty(ret)).unwrap_or_default();
+
+                    format!("|{}| {} end", params, ret)
+                }
+
+                _ => self.ty(fn_expr),
+            },
+
+            // variants are folded to the left, `| a | b c` is ((a, b), c)
+            ExprKind::TypedVariant(lhs, rhs) => match &lhs.kind {
+                ExprKind::TypedVariant(_, _) => format!("{} {}", self.ty(lhs), self.ty(rhs)),
+                _ => format!("| {} | {}", self.ty(lhs), self.ty(rhs)),
+            },
+
+            _ => self.expr(ty, 0),
+        }
+    }
+}
+
+// `(*` would start a comment
+fn parens(text: &str) -> String {
+    if text.starts_with('*') {
+        format!("( {})", text)
+    } else {
+        format!("({})", text)
+    }
+}
+
+fn precedence(expr: &Expr) -> u8 {
+    match &expr.kind {
+        ExprKind::Let(_, _, _)
+        | ExprKind::TypedLet(_, _, _, _)
+        | ExprKind::Assign(_, _)
+        | ExprKind::Directive(_, _) => GREEDY,
+
+        ExprKind::Compare(_, op, _) => match op {
+            CompareOp::Or => 1,
+            CompareOp::And => 2,
+            CompareOp::Eq | CompareOp::Neq => 3,
+            CompareOp::Gt | CompareOp::Lt | CompareOp::Gte | CompareOp::Lte => 4,
+        },
+
+        ExprKind::Bit(_, op, _) => match op {
+            BitOp::Or => 5,
+            BitOp::Xor => 6,
+            BitOp::And => 7,
+            BitOp::Shl | BitOp::Shr | BitOp::Rotl | BitOp::Rotr => 8,
+        },
+
+        ExprKind::Bin(_, op, _) => match op {
+            BinOp::Add | BinOp::Sub => 9,
+            BinOp::Mul | BinOp::Div => 10,
+        },
+
+        ExprKind::Unary(_, _) => UNARY,
+        _ => PRIMARY,
+    }
+}
@@ -0,0 +1,1490 @@
+use crate::diagnostics::Diagnostic;
+use crate::emit::Capture;
+use crate::parser::{method, BinOp, BitOp, CompareOp, Expr, ExprKind, UnaryOp};
+use crate::span::Span;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Write;
+use std::rc::Rc;
+use std::thread;
+
+// evaluates a module straight from the AST, without generating C++, the
+// semantics follow the C++ backend: values are copied on assignment and
+// when passed around, local functions capture variables by reference or
+// value as the lambdas do, a function returns its last expression, `match`
+// dispatches on the type of a value and `esper.print`/`esper.type_of`
+// mirror the __esper class of the prelude
+
+// programs recursing deeper than this are stopped with an error, the
+// interpreter runs on its own thread with a stack large enough for it
+const MAX_CALL_DEPTH: usize = 4096;
+const STACK_SIZE: usize = 512 * 1024 * 1024;
+
+type Result<T> = std::result::Result<T, Diagnostic>;
+
+#[derive(Debug, Clone)]
+enum Value<'a> {
+    Unit,
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+    String(String),
+    List(Vec<Value<'a>>),
+    Record(Vec<(String, Value<'a>)>),
+    Range(i64, i64),
+    Fn(Rc<Closure<'a>>),
+    Struct(Rc<StructDef<'a>>),
+    Instance(Instance<'a>),
+}
+
+#[derive(Debug)]
+struct Closure<'a> {
+    // bound to the closure itself in its body, for recursion
+    name: Option<String>,
+    params: &'a [(String, Option<Expr>)],
+    body: &'a [Expr],
+    return_type: Option<&'a Expr>,
+    // locals of the scope the function was created in, shared with it
+    // or copies of them depending on the capture mode
+    captured: HashMap<String, Local<'a>>,
+    // created in a method, the fields of the instance are captured too
+    captures_this: bool,
+}
+
+#[derive(Debug)]
+struct StructDef<'a> {
+    name: String,
+    fields: Vec<(String, &'a Expr)>,
+    methods: HashMap<String, Rc<Closure<'a>>>,
+}
+
+#[derive(Debug, Clone)]
+struct Instance<'a> {
+    def: Rc<StructDef<'a>>,
+    fields: Vec<(String, Value<'a>)>,
+}
+
+// a local variable, closures hold on to the variables they capture
+type Local<'a> = Rc<RefCell<Value<'a>>>;
+
+// a call frame, block scopes are pushed and popped as blocks are entered,
+// methods run with the instance they were called on as `this`
+#[derive(Debug, Default)]
+struct Frame<'a> {
+    scopes: Vec<HashMap<String, Local<'a>>>,
+    this: Option<Instance<'a>>,
+}
+
+struct Interpreter<'a, W> {
+    out: W,
+    module_id: String,
+    globals: HashMap<String, Value<'a>>,
+    aliases: HashMap<String, &'a Expr>,
+    capture: Capture,
+    depth: usize,
+}
+
+// runs the top-level items of a program in order and then calls its main
+// function with the arguments, returns the exit status from main
+pub fn run(
+    program: &Expr,
+    module_id: &str,
+    args: Vec<String>,
+    capture: Capture,
+    out: impl Write + Send,
+) -> std::result::Result<i32, Diagnostic> {
+    thread::scope(|scope| {
+        let interpreter = thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, move || {
+                let mut interpreter = Interpreter {
+                    out,
+                    module_id: module_id.into(),
+                    globals: HashMap::new(),
+                    aliases: HashMap::new(),
+                    capture,
+                    depth: 0,
+                };
+
+                interpreter.run_main(program, args)
+            })
+            .expect("failed to spawn the interpreter thread");
+
+        match interpreter.join() {
+            Ok(result) => result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    })
+}
+
+impl<'a, W: Write> Interpreter<'a, W> {
+    fn run_main(&mut self, program: &'a Expr, args: Vec<String>) -> Result<i32> {
+        let mut frame = Frame::default();
+        self.eval(&mut frame, program)?;
+
+        let Some(Value::Fn(main)) = self.globals.get("main").cloned() else {
+            return Err(
+                Diagnostic::error("`main` function not found", Span::default())
+                    .with_note("define `let main = |argc, argv| ... end` to run a module"),
+            );
+        };
+
+        // mirrors the entry point generated by the C++ backend
+        let args = match main.params.len() {
+            0 => vec![],
+            _ => vec![
+                Value::Int(args.len() as i64 + 1),
+                Value::List(args.into_iter().map(Value::String).collect()),
+            ],
+        };
+
+        let (status, _) = self.call_fn(&main, args, None, program.span)?;
+        self.out
+            .flush()
+            .map_err(|err| write_error(err, program.span))?;
+
+        match status {
+            Value::Int(code) => Ok(code as i32),
+            _ => Ok(0),
+        }
+    }
+
+    fn eval(&mut self, frame: &mut Frame<'a>, expr: &'a Expr) -> Result<Value<'a>> {
+        let span = expr.span;
+
+        match &expr.kind {
+            ExprKind::Program(items) => {
+                for item in items {
+                    self.eval(frame, item)?;
+                }
+
+                Ok(Value::Unit)
+            }
+
+            ExprKind::Int(n) => Ok(Value::Int(*n)),
+            ExprKind::Float(f) => Ok(Value::Float(*f)),
+            ExprKind::Bool(b) => Ok(Value::Bool(*b)),
+            ExprKind::Char(c) => Ok(Value::Char(*c)),
+            ExprKind::String(s) => Ok(Value::String(unescape(s))),
+            ExprKind::Pass => Ok(Value::Unit),
+            ExprKind::Var(name) => self.lookup(frame, name, span),
+
+            ExprKind::List(items) => items
+                .iter()
+                .map(|item| self.eval(frame, item))
+                .collect::<Result<_>>()
+                .map(Value::List),
+
+            ExprKind::Record(entries) => {
+                let mut fields = vec![];
+
+                for entry in entries {
+                    if let [key, value] = &entry[..] {
+                        let key = match &key.kind {
+                            ExprKind::Var(name) => name.clone(),
+                            ExprKind::Int(n) => n.to_string(),
+                            _ => return Err(Diagnostic::error("invalid record key", key.span)),
+                        };
+
+                        fields.push((key, self.eval(frame, value)?));
+                    }
+                }
+
+                Ok(Value::Record(fields))
+            }
+
+            ExprKind::Range(start, end) => {
+                match (self.eval(frame, start)?, self.eval(frame, end)?) {
+                    (Value::Int(start), Value::Int(end)) => Ok(Value::Range(start, end)),
+                    (start, end) => Err(Diagnostic::error(
+                        format!(
+                            "range bounds must be `int`, found `{}` and `{}`",
+                            type_name(&start),
+                            type_name(&end)
+                        ),
+                        span,
+                    )),
+                }
+            }
+
+            ExprKind::Unary(operand, op) => match (op, self.eval(frame, operand)?) {
+                (UnaryOp::BitNot, value) => Ok(Value::Int(!self.int(&value, operand.span)?)),
+
+                (UnaryOp::Ref | UnaryOp::Deref, _) => Err(Diagnostic::error(
+                    "references and pointers are not supported by the interpreter",
+                    span,
+                )),
+            },
+
+            ExprKind::Bin(lhs, op, rhs) => {
+                let lhs = self.eval(frame, lhs)?;
+                let rhs = self.eval(frame, rhs)?;
+
+                arithmetic(op, lhs, rhs, span)
+            }
+
+            ExprKind::Compare(lhs, CompareOp::And, rhs) => {
+                let lhs_value = self.eval(frame, lhs)?;
+
+                if !truthy(&lhs_value, lhs.span)? {
+                    return Ok(Value::Bool(false));
+                }
+
+                let rhs_value = self.eval(frame, rhs)?;
+                Ok(Value::Bool(truthy(&rhs_value, rhs.span)?))
+            }
+
+            ExprKind::Compare(lhs, CompareOp::Or, rhs) => {
+                let lhs_value = self.eval(frame, lhs)?;
+
+                if truthy(&lhs_value, lhs.span)? {
+                    return Ok(Value::Bool(true));
+                }
+
+                let rhs_value = self.eval(frame, rhs)?;
+                Ok(Value::Bool(truthy(&rhs_value, rhs.span)?))
+            }
+
+            ExprKind::Compare(lhs, op, rhs) => {
+                let lhs = self.eval(frame, lhs)?;
+                let rhs = self.eval(frame, rhs)?;
+
+                compare(op, &lhs, &rhs, span).map(Value::Bool)
+            }
+
+            ExprKind::Bit(lhs, op, rhs) => {
+                let lhs = self.eval(frame, lhs).and_then(|v| self.int(&v, lhs.span))?;
+                let rhs = self.eval(frame, rhs).and_then(|v| self.int(&v, rhs.span))?;
+
+                // the rotates work on 32-bit operands in the C++ backend
+                Ok(Value::Int(match op {
+                    BitOp::Shl => lhs.wrapping_shl(rhs as u32),
+                    BitOp::Shr => lhs.wrapping_shr(rhs as u32),
+                    BitOp::And => lhs & rhs,
+                    BitOp::Or => lhs | rhs,
+                    BitOp::Xor => lhs ^ rhs,
+                    BitOp::Rotl => (lhs as u32).rotate_left(rhs as u32) as i64,
+                    BitOp::Rotr => (lhs as u32).rotate_right(rhs as u32) as i64,
+                }))
+            }
+
+            ExprKind::Let(name, _, value) => {
+                let value = match &value.kind {
+                    ExprKind::Fn(params, body) => {
+                        self.closure(frame, Some(name), params, body, None)
+                    }
+                    _ => self.eval(frame, value)?,
+                };
+
+                self.define(frame, name, value);
+                Ok(Value::Unit)
+            }
+
+            ExprKind::TypedLet(name, _, ty, value) => {
+                let value = match &value.kind {
+                    ExprKind::Fn(params, body) => {
+                        self.closure(frame, Some(name), params, body, Some(ty))
+                    }
+
+                    _ => {
+                        let value = self.eval(frame, value)?;
+                        self.convert(value, ty)
+                    }
+                };
+
+                self.define(frame, name, value);
+                Ok(Value::Unit)
+            }
+
+            ExprKind::Assign(lhs, rhs) => {
+                let value = self.eval(frame, rhs)?;
+                self.assign(frame, lhs, value)?;
+                Ok(Value::Unit)
+            }
+
+            ExprKind::If(cond, then_body, else_body) => {
+                let cond_value = self.eval(frame, cond)?;
+
+                if truthy(&cond_value, cond.span)? {
+                    self.eval_block(frame, then_body, vec![])
+                } else if let Some(else_body) = else_body {
+                    self.eval_block(frame, else_body, vec![])
+                } else {
+                    Ok(Value::Unit)
+                }
+            }
+
+            ExprKind::Loop(pattern, iter, body) => {
+                let items = match self.eval(frame, iter)? {
+                    Value::Range(start, end) => (start..end).map(Value::Int).collect(),
+                    Value::List(items) => items,
+                    Value::String(s) => s.chars().map(Value::Char).collect(),
+                    Value::Record(entries) => map_entries(entries),
+
+                    value => {
+                        return Err(Diagnostic::error(
+                            format!("`{}` is not iterable", type_name(&value)),
+                            iter.span,
+                        ))
+                    }
+                };
+
+                for item in items {
+                    let bindings = bind_pattern(pattern, item)?;
+                    self.eval_block(frame, body, bindings)?;
+                }
+
+                Ok(Value::Unit)
+            }
+
+            ExprKind::Match(cond, cases) => {
+                let value = self.eval(frame, cond)?;
+                let value_type = type_name(&value);
+
+                for (pattern, body) in cases {
+                    if self.matches_type(pattern, &value_type) {
+                        // the matched value is `_` in the generated visitor
+                        return self.eval_block(frame, body, vec![("_".into(), value)]);
+                    }
+                }
+
+                Ok(Value::Unit)
+            }
+
+            ExprKind::Fn(params, body) => Ok(self.closure(frame, None, params, body, None)),
+
+            ExprKind::Call(callee, args) | ExprKind::TypedCall(callee, _, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval(frame, arg))
+                    .collect::<Result<Vec<_>>>()?;
+
+                self.eval_call(frame, callee, args, span)
+            }
+
+            ExprKind::Member(members) => {
+                let mut chain = vec![];
+                flatten_members(members, &mut chain);
+
+                self.eval_member(frame, &chain, span)
+            }
+
+            ExprKind::Construct(name, fields) => {
+                let fields = self.eval(frame, fields)?;
+
+                match self.lookup(frame, name, span)? {
+                    Value::Struct(def) => Ok(self.initialize(&def, fields)),
+
+                    value => Err(Diagnostic::error(
+                        format!("`{}` is not a struct", type_name(&value)),
+                        span,
+                    )),
+                }
+            }
+
+            ExprKind::Struct(name, _, entries) => {
+                let mut fields = vec![];
+                let mut methods = HashMap::new();
+
+                for (entry_name, entry) in entries {
+                    match method(entry) {
+                        Some((params, body, return_type)) => {
+                            let method = Closure {
+                                name: None,
+                                params,
+                                body,
+                                return_type,
+                                captured: HashMap::new(),
+                                captures_this: false,
+                            };
+
+                            methods.insert(entry_name.clone(), Rc::new(method));
+                        }
+
+                        None => fields.push((entry_name.clone(), entry)),
+                    }
+                }
+
+                let def = StructDef {
+                    name: name.clone(),
+                    fields,
+                    methods,
+                };
+
+                self.define(frame, name, Value::Struct(Rc::new(def)));
+                Ok(Value::Unit)
+            }
+
+            // record aliases are structs of their fields, as in C++
+            ExprKind::TypeAlias(name, _, rhs) => {
+                match &rhs.kind {
+                    ExprKind::TypedRecord(record) => {
+                        let ExprKind::Record(entries) = &record.kind else {
+                            return Ok(Value::Unit);
+                        };
+                        let fields = entries
+                            .iter()
+                            .filter_map(|entry| match &entry[..] {
+                                [key, ty] => match &key.kind {
+                                    ExprKind::Var(field) => Some((field.clone(), ty)),
+                                    _ => None,
+                                },
+                                _ => None,
+                            })
+                            .collect();
+                        let def = StructDef {
+                            name: name.clone(),
+                            fields,
+                            methods: HashMap::new(),
+                        };
+
+                        self.define(frame, name, Value::Struct(Rc::new(def)));
+                    }
+
+                    _ => {
+                        self.aliases.insert(name.clone(), rhs);
+                    }
+                }
+
+                Ok(Value::Unit)
+            }
+
+            // modifiers (@const, @inline, @extend, ...) only matter to C++
+            ExprKind::Directive(_, inner) => self.eval(frame, inner),
+
+            // types have no value
+            _ => Ok(Value::Unit),
+        }
+    }
+
+    // runs the statements of a block in a scope of their own, the value of
+    // the block is the value of its last statement
+    fn eval_block(
+        &mut self,
+        frame: &mut Frame<'a>,
+        body: &'a [Expr],
+        bindings: Vec<(String, Value<'a>)>,
+    ) -> Result<Value<'a>> {
+        frame.scopes.push(
+            bindings
+                .into_iter()
+                .map(|(name, value)| (name, Rc::new(RefCell::new(value))))
+                .collect(),
+        );
+
+        let mut result = Ok(Value::Unit);
+
+        for expr in body {
+            result = self.eval(frame, expr);
+
+            if result.is_err() {
+                break;
+            }
+        }
+
+        frame.scopes.pop();
+        result
+    }
+
+    // the last expression is only returned when the C++ backend would emit
+    // a `return` for it, statements leave the function without a value
+    fn eval_fn_body(&mut self, frame: &mut Frame<'a>, body: &'a [Expr]) -> Result<Value<'a>> {
+        let Some((last, rest)) = body.split_last() else {
+            return Ok(Value::Unit);
+        };
+
+        for expr in rest {
+            self.eval(frame, expr)?;
+        }
+
+        let value = self.eval(frame, last)?;
+
+        Ok(if last.is_value() { value } else { Value::Unit })
+    }
+
+    fn closure(
+        &self,
+        frame: &Frame<'a>,
+        name: Option<&str>,
+        params: &'a [(String, Option<Expr>)],
+        body: &'a [Expr],
+        return_type: Option<&'a Expr>,
+    ) -> Value<'a> {
+        let captured = frame
+            .scopes
+            .iter()
+            .flat_map(|scope| scope.iter())
+            .map(|(name, local)| {
+                let local = match self.capture {
+                    Capture::Reference => local.clone(),
+                    Capture::Value => Rc::new(RefCell::new(local.borrow().clone())),
+                };
+
+                (name.clone(), local)
+            })
+            .collect();
+
+        Value::Fn(Rc::new(Closure {
+            name: name.map(Into::into),
+            params,
+            body,
+            return_type,
+            captured,
+            captures_this: frame.this.is_some(),
+        }))
+    }
+
+    fn call_fn(
+        &mut self,
+        closure: &Rc<Closure<'a>>,
+        args: Vec<Value<'a>>,
+        this: Option<Instance<'a>>,
+        span: Span,
+    ) -> Result<(Value<'a>, Option<Instance<'a>>)> {
+        if args.len() != closure.params.len() {
+            return Err(Diagnostic::error(
+                format!(
+                    "this function takes {} argument{} but {} {} supplied",
+                    closure.params.len(),
+                    if closure.params.len() == 1 { "" } else { "s" },
+                    args.len(),
+                    if args.len() == 1 { "was" } else { "were" }
+                ),
+                span,
+            ));
+        }
+
+        if self.depth == MAX_CALL_DEPTH {
+            return Err(Diagnostic::error("stack overflow", span)
+                .with_note(format!("calls are nested deeper than {}", MAX_CALL_DEPTH)));
+        }
+
+        let mut scope = closure.captured.clone();
+
+        if let Some(name) = &closure.name {
+            scope.insert(
+                name.clone(),
+                Rc::new(RefCell::new(Value::Fn(closure.clone()))),
+            );
+        }
+
+        for ((param, ty), arg) in closure.params.iter().zip(args) {
+            let arg = match ty {
+                Some(ty) => self.convert(arg, ty),
+                None => arg,
+            };
+
+            scope.insert(param.clone(), Rc::new(RefCell::new(arg)));
+        }
+
+        let mut frame = Frame {
+            scopes: vec![scope],
+            this,
+        };
+
+        self.depth += 1;
+        let result = self.eval_fn_body(&mut frame, closure.body);
+        self.depth -= 1;
+
+        let value = match closure.return_type {
+            Some(ty) => self.convert(result?, ty),
+            None => result?,
+        };
+
+        Ok((value, frame.this))
+    }
+
+    fn eval_call(
+        &mut self,
+        frame: &mut Frame<'a>,
+        callee: &'a Expr,
+        args: Vec<Value<'a>>,
+        span: Span,
+    ) -> Result<Value<'a>> {
+        let callee_value = match &callee.kind {
+            // other methods are called without `this.` in a method body
+            ExprKind::Var(name) if !frame.scopes.iter().any(|scope| scope.contains_key(name)) => {
+                let method = frame
+                    .this
+                    .as_ref()
+                    .and_then(|this| this.def.methods.get(name).cloned());
+
+                if let Some(method) = method {
+                    let this = frame.this.take();
+                    let (value, this) = self.call_fn(&method, args, this, span)?;
+                    frame.this = this;
+
+                    return Ok(value);
+                }
+
+                self.lookup(frame, name, callee.span)?
+            }
+
+            _ => self.eval(frame, callee)?,
+        };
+
+        match callee_value {
+            Value::Fn(closure) if closure.captures_this && frame.this.is_some() => {
+                let this = frame.this.take();
+                let (value, this) = self.call_fn(&closure, args, this, span)?;
+                frame.this = this;
+
+                Ok(value)
+            }
+
+            Value::Fn(closure) => Ok(self.call_fn(&closure, args, None, span)?.0),
+
+            // aggregate initialization, missing fields get their default
+            Value::Struct(def) => {
+                if args.len() > def.fields.len() {
+                    return Err(Diagnostic::error(
+                        format!(
+                            "`{}` has {} field{} but {} values were supplied",
+                            def.name,
+                            def.fields.len(),
+                            if def.fields.len() == 1 { "" } else { "s" },
+                            args.len()
+                        ),
+                        span,
+                    ));
+                }
+
+                let mut instance = self.instantiate(&def);
+
+                for ((_, field), arg) in instance.fields.iter_mut().zip(args) {
+                    *field = arg;
+                }
+
+                Ok(Value::Instance(instance))
+            }
+
+            value => Err(Diagnostic::error(
+                format!("`{}` is not callable", type_name(&value)),
+                callee.span,
+            )),
+        }
+    }
+
+    // `a.b.f(x)`, methods called on a variable (or a field of one) update
+    // it with the instance they leave behind
+    fn eval_member(
+        &mut self,
+        frame: &mut Frame<'a>,
+        chain: &[&'a Expr],
+        span: Span,
+    ) -> Result<Value<'a>> {
+        let Some((first, rest)) = chain.split_first() else {
+            return Ok(Value::Unit);
+        };
+
+        if let ExprKind::Var(name) = &first.kind {
+            if name == "esper" && self.lookup(frame, name, first.span).is_err() {
+                return match rest {
+                    [call] => self.eval_prelude(frame, call),
+                    _ => Err(Diagnostic::error("expected a prelude function call", span)),
+                };
+            }
+        }
+
+        let mut place = match &first.kind {
+            ExprKind::Var(name) => Some((name.as_str(), vec![])),
+            _ => None,
+        };
+
+        let mut current = self.eval(frame, first)?;
+
+        for member in rest {
+            match &member.kind {
+                ExprKind::Var(field) => {
+                    current = field_of(&current, field, member.span)?;
+
+                    if let Some((_, fields)) = &mut place {
+                        fields.push(field.as_str());
+                    }
+                }
+
+                ExprKind::Call(method, args) | ExprKind::TypedCall(method, _, args) => {
+                    let ExprKind::Var(method) = &method.kind else {
+                        return Err(Diagnostic::error("expected a method name", method.span));
+                    };
+
+                    let args = args
+                        .iter()
+                        .map(|arg| self.eval(frame, arg))
+                        .collect::<Result<Vec<_>>>()?;
+
+                    let (value, receiver) = self.call_method(current, method, args, member.span)?;
+
+                    if let (Some((name, fields)), Some(receiver)) = (place.take(), receiver) {
+                        self.write_place(frame, name, &fields, receiver, span)?;
+                    }
+
+                    current = value;
+                }
+
+                _ => {
+                    return Err(Diagnostic::error(
+                        "unsupported member expression",
+                        member.span,
+                    ))
+                }
+            }
+        }
+
+        Ok(current)
+    }
+
+    // returns the value of the call along with the receiver when the call
+    // may have changed it
+    fn call_method(
+        &mut self,
+        receiver: Value<'a>,
+        method: &str,
+        args: Vec<Value<'a>>,
+        span: Span,
+    ) -> Result<(Value<'a>, Option<Value<'a>>)> {
+        match (receiver, method, &args[..]) {
+            (Value::Instance(instance), _, _) if instance.def.methods.contains_key(method) => {
+                let closure = instance.def.methods[method].clone();
+                let (value, this) = self.call_fn(&closure, args, Some(instance), span)?;
+
+                Ok((value, this.map(Value::Instance)))
+            }
+
+            // records in value position are maps in the C++ backend
+            (Value::Record(entries), "at", [key]) => {
+                match entries
+                    .into_iter()
+                    .find(|(name, _)| *name == record_key(key))
+                {
+                    Some((_, value)) => Ok((value, None)),
+                    None => Err(Diagnostic::error(
+                        format!("no entry for key `{}`", record_key(key)),
+                        span,
+                    )),
+                }
+            }
+
+            (Value::Record(entries), "contains", [key]) => Ok((
+                Value::Bool(entries.iter().any(|(name, _)| *name == record_key(key))),
+                None,
+            )),
+
+            (Value::Record(entries), "size", []) => Ok((Value::Int(entries.len() as i64), None)),
+            (Value::Record(entries), "empty", []) => Ok((Value::Bool(entries.is_empty()), None)),
+
+            // fields and record entries holding functions
+            (receiver @ (Value::Instance(_) | Value::Record(_)), _, _) => {
+                match field_of(&receiver, method, span)? {
+                    Value::Fn(closure) => Ok((self.call_fn(&closure, args, None, span)?.0, None)),
+
+                    value => Err(Diagnostic::error(
+                        format!("`{}` is not callable", type_name(&value)),
+                        span,
+                    )),
+                }
+            }
+
+            // the few std::vector and std::string members worth having
+            (Value::List(items), "size", []) => Ok((Value::Int(items.len() as i64), None)),
+            (Value::List(items), "empty", []) => Ok((Value::Bool(items.is_empty()), None)),
+            // sizes and indices of strings are in bytes as in C++
+            (Value::String(s), "size", []) => Ok((Value::Int(s.len() as i64), None)),
+            (Value::String(s), "empty", []) => Ok((Value::Bool(s.is_empty()), None)),
+
+            (Value::List(items), "at", [index]) => {
+                let index = self.index(index, items.len(), span)?;
+                Ok((items[index].clone(), None))
+            }
+
+            (Value::String(s), "at", [index]) => {
+                let index = self.index(index, s.len(), span)?;
+                Ok((Value::Char(s.as_bytes()[index] as char), None))
+            }
+
+            (Value::List(mut items), "push_back", [item]) => {
+                items.push(item.clone());
+                Ok((Value::Unit, Some(Value::List(items))))
+            }
+
+            (receiver, _, _) => Err(Diagnostic::error(
+                format!(
+                    "no method `{}` on a value of type `{}`",
+                    method,
+                    type_name(&receiver)
+                ),
+                span,
+            )),
+        }
+    }
+
+    // esper.print and esper.type_of from the prelude
+    fn eval_prelude(&mut self, frame: &mut Frame<'a>, call: &'a Expr) -> Result<Value<'a>> {
+        let (ExprKind::Call(callee, args) | ExprKind::TypedCall(callee, _, args)) = &call.kind
+        else {
+            return Err(Diagnostic::error(
+                "expected a prelude function call",
+                call.span,
+            ));
+        };
+
+        let args = args
+            .iter()
+            .map(|arg| self.eval(frame, arg))
+            .collect::<Result<Vec<_>>>()?;
+
+        match (&callee.kind, &args[..]) {
+            (ExprKind::Var(name), _) if name == "print" => {
+                let text = args
+                    .iter()
+                    .map(|arg| display(arg, call.span))
+                    .collect::<Result<Vec<_>>>()?
+                    .join(" ");
+
+                self.out
+                    .write_all(text.as_bytes())
+                    .map_err(|err| write_error(err, call.span))?;
+
+                Ok(Value::Unit)
+            }
+
+            (ExprKind::Var(name), [value]) if name == "type_of" => {
+                Ok(Value::String(self.cxx_type_name(value)))
+            }
+
+            _ => Err(Diagnostic::error(
+                "unknown prelude function, expected `print` or `type_of`",
+                callee.span,
+            )),
+        }
+    }
+
+    fn define(&mut self, frame: &mut Frame<'a>, name: &str, value: Value<'a>) {
+        match frame.scopes.last_mut() {
+            Some(scope) => {
+                scope.insert(name.into(), Rc::new(RefCell::new(value)));
+            }
+
+            None => {
+                self.globals.insert(name.into(), value);
+            }
+        }
+    }
+
+    fn lookup(&self, frame: &Frame<'a>, name: &str, span: Span) -> Result<Value<'a>> {
+        let local = frame.scopes.iter().rev().find_map(|scope| scope.get(name));
+        let field = frame.this.as_ref().and_then(|this| {
+            this.fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| value)
+        });
+
+        let value = match local {
+            Some(local) => Some(local.borrow().clone()),
+
+            // the receiver of a method
+            None if name == "self" && frame.this.is_some() => {
+                frame.this.clone().map(Value::Instance)
+            }
+
+            None => field.or_else(|| self.globals.get(name)).cloned(),
+        };
+
+        match value {
+            Some(value) => Ok(value),
+            None if name == "nullopt" => Ok(Value::Unit),
+            None => Err(Diagnostic::error(
+                format!("cannot find value `{}` in this scope", name),
+                span,
+            )),
+        }
+    }
+
+    fn place_mut<'f>(
+        &'f mut self,
+        frame: &'f mut Frame<'a>,
+        name: &str,
+    ) -> Option<&'f mut Value<'a>> {
+        if let Some(this) = &mut frame.this {
+            if let Some((_, value)) = this.fields.iter_mut().find(|(field, _)| field == name) {
+                return Some(value);
+            }
+        }
+
+        self.globals.get_mut(name)
+    }
+
+    fn write_place(
+        &mut self,
+        frame: &mut Frame<'a>,
+        name: &str,
+        fields: &[&str],
+        value: Value<'a>,
+        span: Span,
+    ) -> Result<()> {
+        let local = frame.scopes.iter().rev().find_map(|scope| scope.get(name));
+
+        if let Some(local) = local.cloned() {
+            return write_fields(&mut local.borrow_mut(), fields, value, span);
+        }
+
+        // fields of the receiver, or the receiver itself after a call
+        if name == "self" && frame.this.is_some() {
+            return match (fields.split_first(), value) {
+                (Some((field, fields)), value) => match self.place_mut(frame, field) {
+                    Some(place) => write_fields(place, fields, value, span),
+                    None => Err(Diagnostic::error(format!("no field `{}`", field), span)),
+                },
+
+                (None, Value::Instance(instance)) => {
+                    frame.this = Some(instance);
+                    Ok(())
+                }
+
+                (None, _) => Err(Diagnostic::error("cannot assign to `self`", span)),
+            };
+        }
+
+        match self.place_mut(frame, name) {
+            Some(place) => write_fields(place, fields, value, span),
+            None => Err(Diagnostic::error(
+                format!("cannot find value `{}` in this scope", name),
+                span,
+            )),
+        }
+    }
+
+    fn assign(&mut self, frame: &mut Frame<'a>, lhs: &'a Expr, value: Value<'a>) -> Result<()> {
+        match &lhs.kind {
+            ExprKind::Var(name) => self.write_place(frame, name, &[], value, lhs.span),
+
+            ExprKind::Member(members) => {
+                let mut chain = vec![];
+                flatten_members(members, &mut chain);
+
+                let names = chain
+                    .iter()
+                    .map(|member| match &member.kind {
+                        ExprKind::Var(name) => Some(name.as_str()),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<_>>>();
+
+                match names.as_deref() {
+                    Some([name, fields @ ..]) => {
+                        self.write_place(frame, name, fields, value, lhs.span)
+                    }
+                    _ => Err(Diagnostic::error(
+                        "cannot assign to this expression",
+                        lhs.span,
+                    )),
+                }
+            }
+
+            _ => Err(Diagnostic::error(
+                "cannot assign to this expression",
+                lhs.span,
+            )),
+        }
+    }
+
+    // a record initializing a struct by field, missing fields get their
+    // default
+    fn initialize(&self, def: &Rc<StructDef<'a>>, value: Value<'a>) -> Value<'a> {
+        let Value::Record(entries) = value else {
+            return value;
+        };
+
+        let mut instance = self.instantiate(def);
+
+        for ((name, field), (_, ty)) in instance.fields.iter_mut().zip(&def.fields) {
+            if let Some((_, value)) = entries.iter().find(|(key, _)| key == name) {
+                *field = self.convert(value.clone(), ty);
+            }
+        }
+
+        Value::Instance(instance)
+    }
+
+    fn instantiate(&self, def: &Rc<StructDef<'a>>) -> Instance<'a> {
+        Instance {
+            def: def.clone(),
+            fields: def
+                .fields
+                .iter()
+                .map(|(name, ty)| (name.clone(), self.default_value(ty)))
+                .collect(),
+        }
+    }
+
+    // the value a variable of the type starts with, std::variant holds its
+    // first alternative when default constructed
+    fn default_value(&self, ty: &Expr) -> Value<'a> {
+        match &ty.kind {
+            ExprKind::TypedSymbol(name) => match name.as_str() {
+                name if is_int_type(name) => Value::Int(0),
+                "float" | "double" => Value::Float(0.0),
+                "bool" => Value::Bool(false),
+                "char" => Value::Char('\0'),
+                "string" => Value::String(String::new()),
+                "vector" => Value::List(vec![]),
+
+                name => match (self.aliases.get(name), self.globals.get(name)) {
+                    (Some(alias), _) => self.default_value(alias),
+                    (_, Some(Value::Struct(def))) => Value::Instance(self.instantiate(def)),
+                    _ => Value::Unit,
+                },
+            },
+
+            ExprKind::TypedSymbolGeneric(name, _) if name == "vector" => Value::List(vec![]),
+            ExprKind::TypedSymbolGeneric(name, _) if is_map(name) => Value::Record(vec![]),
+            ExprKind::TypedVariant(first, _) => self.default_value(first),
+
+            ExprKind::TypedLiteral(literal) => match literal.kind {
+                ExprKind::Int(_) => Value::Int(0),
+                ExprKind::Float(_) => Value::Float(0.0),
+                ExprKind::Bool(_) => Value::Bool(false),
+                _ => Value::Unit,
+            },
+
+            _ => Value::Unit,
+        }
+    }
+
+    // implicit conversions of a value to a declared type
+    fn convert(&self, value: Value<'a>, ty: &Expr) -> Value<'a> {
+        let name = match &ty.kind {
+            ExprKind::TypedSymbol(name) => name,
+
+            // entries of a map convert to its value type
+            ExprKind::TypedSymbolGeneric(name, args) if is_map(name) => {
+                return match (value, args.get(1)) {
+                    (Value::Record(entries), Some(value_type)) => Value::Record(
+                        entries
+                            .into_iter()
+                            .map(|(key, value)| (key, self.convert(value, value_type)))
+                            .collect(),
+                    ),
+                    (value, _) => value,
+                };
+            }
+
+            // generic structs are initialized as any other
+            ExprKind::TypedSymbolGeneric(name, _) => name,
+
+            _ => return value,
+        };
+
+        match (value, name.as_str()) {
+            (Value::Float(f), name) if is_int_type(name) => Value::Int(f as i64),
+            (Value::Int(n), "float" | "double") => Value::Float(n as f64),
+
+            (value, name) => match (self.aliases.get(name), self.globals.get(name)) {
+                (Some(alias), _) => self.convert(value, alias),
+
+                (_, Some(Value::Struct(def))) => self.initialize(def, value),
+
+                _ => value,
+            },
+        }
+    }
+
+    // patterns name types, aliases are resolved to what they stand for
+    fn matches_type(&self, pattern: &str, value_type: &str) -> bool {
+        match self.aliases.get(pattern).map(|alias| &alias.kind) {
+            Some(ExprKind::TypedSymbol(name)) if name != pattern => {
+                self.matches_type(name, value_type)
+            }
+
+            _ => pattern == value_type || (pattern == "float" && value_type == "double"),
+        }
+    }
+
+    // names as printed by esper.type_of for the C++ backend where they
+    // are known, structs live in the module namespace
+    fn cxx_type_name(&self, value: &Value<'a>) -> String {
+        match value {
+            Value::String(_) => "std::string".into(),
+            Value::List(_) => "std::vector".into(),
+            Value::Instance(instance) => format!("{}::{}", self.module_id, instance.def.name),
+            value => type_name(value),
+        }
+    }
+
+    fn int(&self, value: &Value<'a>, span: Span) -> Result<i64> {
+        match value {
+            Value::Int(n) => Ok(*n),
+            Value::Bool(b) => Ok(*b as i64),
+            Value::Char(c) => Ok(*c as i64),
+
+            value => Err(Diagnostic::error(
+                format!("expected `int`, found `{}`", type_name(value)),
+                span,
+            )),
+        }
+    }
+
+    // `at` throws std::out_of_range past the end in C++
+    fn index(&self, index: &Value<'a>, len: usize, span: Span) -> Result<usize> {
+        let index = self.int(index, span)?;
+
+        match usize::try_from(index) {
+            Ok(index) if index < len => Ok(index),
+            _ => Err(Diagnostic::error(
+                format!("index {} is out of range for size {}", index, len),
+                span,
+            )),
+        }
+    }
+}
+
+// assigns to a field of a value, or to the value itself without fields
+fn write_fields<'a>(
+    mut place: &mut Value<'a>,
+    fields: &[&str],
+    value: Value<'a>,
+    span: Span,
+) -> Result<()> {
+    for field in fields {
+        let entries = match place {
+            Value::Instance(instance) => &mut instance.fields,
+            Value::Record(entries) => entries,
+            _ => return Err(Diagnostic::error(format!("no field `{}`", field), span)),
+        };
+
+        place = match entries.iter_mut().find(|(name, _)| name == field) {
+            Some((_, value)) => value,
+            None => return Err(Diagnostic::error(format!("no field `{}`", field), span)),
+        };
+    }
+
+    *place = value;
+    Ok(())
+}
+
+fn flatten_members<'a>(members: &'a [Expr], chain: &mut Vec<&'a Expr>) {
+    for member in members {
+        match &member.kind {
+            ExprKind::Member(inner) => flatten_members(inner, chain),
+            _ => chain.push(member),
+        }
+    }
+}
+
+fn field_of<'a>(value: &Value<'a>, field: &str, span: Span) -> Result<Value<'a>> {
+    let entries = match value {
+        Value::Instance(instance) => &instance.fields,
+        Value::Record(entries) => entries,
+
+        value => {
+            return Err(Diagnostic::error(
+                format!("`{}` has no fields", type_name(value)),
+                span,
+            ))
+        }
+    };
+
+    match entries.iter().find(|(name, _)| name == field) {
+        Some((_, value)) => Ok(value.clone()),
+        None => Err(Diagnostic::error(format!("no field `{}`", field), span)),
+    }
+}
+
+fn bind_pattern<'a>(pattern: &Expr, value: Value<'a>) -> Result<Vec<(String, Value<'a>)>> {
+    match (&pattern.kind, value) {
+        (ExprKind::Var(name), value) => Ok(vec![(name.clone(), value)]),
+
+        (ExprKind::List(names), Value::List(items)) if names.len() == items.len() => names
+            .iter()
+            .zip(items)
+            .map(|(name, item)| bind_pattern(name, item))
+            .collect::<Result<Vec<_>>>()
+            .map(|bindings| bindings.into_iter().flatten().collect()),
+
+        (ExprKind::List(names), Value::Record(entries)) if names.len() == entries.len() => names
+            .iter()
+            .zip(entries)
+            .map(|(name, (_, item))| bind_pattern(name, item))
+            .collect::<Result<Vec<_>>>()
+            .map(|bindings| bindings.into_iter().flatten().collect()),
+
+        (_, value) => Err(Diagnostic::error(
+            format!("cannot destructure a value of type `{}`", type_name(&value)),
+            pattern.span,
+        )),
+    }
+}
+
+fn truthy(value: &Value, span: Span) -> Result<bool> {
+    match value {
+        Value::Bool(b) => Ok(*b),
+        Value::Int(n) => Ok(*n != 0),
+        Value::Float(f) => Ok(*f != 0.0),
+        Value::Char(c) => Ok(*c != '\0'),
+
+        value => Err(Diagnostic::error(
+            format!("expected `bool`, found `{}`", type_name(value)),
+            span,
+        )),
+    }
+}
+
+// bools and chars take part in arithmetic as integers, like in C++
+fn numeric(value: &Value) -> Option<Value<'static>> {
+    match value {
+        Value::Int(n) => Some(Value::Int(*n)),
+        Value::Float(f) => Some(Value::Float(*f)),
+        Value::Bool(b) => Some(Value::Int(*b as i64)),
+        Value::Char(c) => Some(Value::Int(*c as i64)),
+        _ => None,
+    }
+}
+
+fn arithmetic<'a>(op: &BinOp, lhs: Value<'a>, rhs: Value<'a>, span: Span) -> Result<Value<'a>> {
+    let symbol = match op {
+        BinOp::Add => "+",
+        BinOp::Sub => "-",
+        BinOp::Mul => "*",
+        BinOp::Div => "/",
+    };
+
+    match (op, numeric(&lhs), numeric(&rhs)) {
+        (BinOp::Div, Some(Value::Int(_)), Some(Value::Int(0))) => {
+            Err(Diagnostic::error("attempt to divide by zero", span))
+        }
+
+        (_, Some(Value::Int(a)), Some(Value::Int(b))) => Ok(Value::Int(match op {
+            BinOp::Add => a.wrapping_add(b),
+            BinOp::Sub => a.wrapping_sub(b),
+            BinOp::Mul => a.wrapping_mul(b),
+            BinOp::Div => a.wrapping_div(b),
+        })),
+
+        (_, Some(a), Some(b)) => {
+            let (a, b) = (as_float(&a), as_float(&b));
+
+            Ok(Value::Float(match op {
+                BinOp::Add => a + b,
+                BinOp::Sub => a - b,
+                BinOp::Mul => a * b,
+                BinOp::Div => a / b,
+            }))
+        }
+
+        _ => match (op, lhs, rhs) {
+            (BinOp::Add, Value::String(a), Value::String(b)) => Ok(Value::String(a + &b)),
+            (BinOp::Add, Value::String(a), Value::Char(b)) => Ok(Value::String(a + &b.to_string())),
+            (BinOp::Add, Value::Char(a), Value::String(b)) => Ok(Value::String(a.to_string() + &b)),
+
+            (_, lhs, rhs) => Err(Diagnostic::error(
+                format!(
+                    "cannot apply `{}` to `{}` and `{}`",
+                    symbol,
+                    type_name(&lhs),
+                    type_name(&rhs)
+                ),
+                span,
+            )),
+        },
+    }
+}
+
+fn compare(op: &CompareOp, lhs: &Value, rhs: &Value, span: Span) -> Result<bool> {
+    let ordering = match (numeric(lhs), numeric(rhs), lhs, rhs) {
+        (Some(Value::Int(a)), Some(Value::Int(b)), _, _) => a.partial_cmp(&b),
+        (Some(a), Some(b), _, _) => as_float(&a).partial_cmp(&as_float(&b)),
+        (_, _, Value::String(a), Value::String(b)) => a.partial_cmp(b),
+
+        _ => {
+            return Err(Diagnostic::error(
+                format!(
+                    "cannot compare `{}` and `{}`",
+                    type_name(lhs),
+                    type_name(rhs)
+                ),
+                span,
+            ))
+        }
+    };
+
+    // NaN compares false to anything, unequal included
+    let Some(ordering) = ordering else {
+        return Ok(*op == CompareOp::Neq);
+    };
+
+    Ok(match op {
+        CompareOp::Gt => ordering.is_gt(),
+        CompareOp::Lt => ordering.is_lt(),
+        CompareOp::Gte => ordering.is_ge(),
+        CompareOp::Lte => ordering.is_le(),
+        CompareOp::Eq => ordering.is_eq(),
+        CompareOp::Neq => ordering.is_ne(),
+        CompareOp::And | CompareOp::Or => unreachable!(),
+    })
+}
+
+fn as_float(value: &Value) -> f64 {
+    match value {
+        Value::Int(n) => *n as f64,
+        Value::Float(f) => *f,
+        _ => 0.0,
+    }
+}
+
+fn is_map(name: &str) -> bool {
+    matches!(name, "map" | "unordered_map")
+}
+
+// the key of a record entry a value looks up, names are string keys
+fn record_key(key: &Value) -> String {
+    match key {
+        Value::String(s) => s.clone(),
+        Value::Int(n) => n.to_string(),
+        Value::Char(c) => c.to_string(),
+        value => type_name(value),
+    }
+}
+
+// the entries of a record as key and value pairs in key order, integer
+// keys are those of all digits as names cannot start with one
+fn map_entries(entries: Vec<(String, Value)>) -> Vec<Value> {
+    let mut entries = entries
+        .into_iter()
+        .map(|(key, value)| match key.parse::<i64>() {
+            Ok(n) => (Value::Int(n), value),
+            Err(_) => (Value::String(key), value),
+        })
+        .collect::<Vec<_>>();
+
+    entries.sort_by(|(a, _), (b, _)| match (a, b) {
+        (Value::Int(a), Value::Int(b)) => a.cmp(b),
+        (a, b) => record_key(a).cmp(&record_key(b)),
+    });
+
+    entries
+        .into_iter()
+        .map(|(key, value)| Value::List(vec![key, value]))
+        .collect()
+}
+
+fn is_int_type(name: &str) -> bool {
+    matches!(
+        name,
+        "int"
+            | "long"
+            | "short"
+            | "unsigned"
+            | "size_t"
+            | "int32_t"
+            | "int64_t"
+            | "uint32_t"
+            | "uint64_t"
+    )
+}
+
+// names used in diagnostics and matched against `match` patterns
+fn type_name(value: &Value) -> String {
+    match value {
+        Value::Unit => "void".into(),
+        Value::Int(_) => "int".into(),
+        Value::Float(_) => "double".into(),
+        Value::Bool(_) => "bool".into(),
+        Value::Char(_) => "char".into(),
+        Value::String(_) => "string".into(),
+        Value::List(_) => "vector".into(),
+        Value::Record(_) => "record".into(),
+        Value::Range(_, _) => "range".into(),
+        Value::Fn(_) => "function".into(),
+        Value::Struct(def) => def.name.clone(),
+        Value::Instance(instance) => instance.def.name.clone(),
+    }
+}
+
+// what std::cout prints for the value, other values have no operator<<
+// in the C++ backend either
+fn display(value: &Value, span: Span) -> Result<String> {
+    match value {
+        Value::Int(n) => Ok(n.to_string()),
+        Value::Float(f) => Ok(format_float(*f)),
+        Value::Bool(b) => Ok(if *b { "1" } else { "0" }.into()),
+        Value::Char(c) => Ok(c.to_string()),
+        Value::String(s) => Ok(s.clone()),
+
+        value => Err(Diagnostic::error(
+            format!("cannot print a value of type `{}`", type_name(value)),
+            span,
+        )),
+    }
+}
+
+// std::cout defaults to %g with 6 significant digits
+fn format_float(f: f64) -> String {
+    if f.is_nan() {
+        return "nan".into();
+    }
+
+    if f.is_infinite() {
+        return if f > 0.0 { "inf" } else { "-inf" }.into();
+    }
+
+    if f == 0.0 {
+        return if f.is_sign_negative() { "-0" } else { "0" }.into();
+    }
+
+    let scientific = format!("{:.5e}", f);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+
+    if !(-4..6).contains(&exponent) {
+        let mantissa = trim_fraction(mantissa);
+        let sign = if exponent < 0 { '-' } else { '+' };
+
+        format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+    } else {
+        trim_fraction(&format!("{:.*}", (5 - exponent) as usize, f)).into()
+    }
+}
+
+fn trim_fraction(s: &str) -> &str {
+    if s.contains('.') {
+        s.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        s
+    }
+}
+
+// string literals are emitted as C++ literals, their escapes included
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('0') => out.push('\0'),
+            Some('\\') => out.push('\\'),
+            Some('\'') => out.push('\''),
+            Some(c) => {
+                out.push('\\');
+                out.push(c);
+            }
+            None => out.push('\\'),
+        }
+    }
+
+    out
+}
+
+fn write_error(err: std::io::Error, span: Span) -> Diagnostic {
+    Diagnostic::error(format!("failed to write output: {}", err), span)
+}
@@ -0,0 +1,109 @@
+// esper compiles modules of a minimal expression-based language to C++,
+// the binary is a thin command line wrapper over parse(), emit_cpp() and
+// Session (for builds driving the C++ compiler)
+
+pub mod cc;
+pub mod check;
+pub mod diagnostics;
+pub mod emit;
+pub mod error;
+pub mod exhaustive;
+pub mod format;
+pub mod interp;
+pub mod json;
+pub mod lsp;
+pub mod parser;
+pub mod repl;
+pub mod resolve;
+pub mod session;
+pub mod span;
+pub mod types;
+pub mod visit;
+
+pub use crate::cc::{CxxCompiler, CxxFlavor};
+pub use crate::diagnostics::{Diagnostic, Diagnostics, MessageFormat, Severity};
+pub use crate::emit::Capture;
+pub use crate::error::CompileError;
+pub use crate::session::{FileId, Session};
+
+use crate::emit::{EmitContextImpl, EmitDefault};
+use crate::parser::{parse_program, Expr};
+use crate::span::LineIndex;
+
+// a parsed module, the line index is kept around so the emitted C++ can
+// point back into the source that was parsed
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub root: Expr,
+    source: String,
+    lines: LineIndex,
+}
+
+impl Program {
+    // runs the semantic passes, warnings are included along with errors,
+    // names the module does not define are warnings when allowed
+    pub fn check(&self, allow_undefined: bool) -> Diagnostics {
+        check::check_program(&self.root, &self.source, allow_undefined).into()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    // namespace the module is emitted into
+    pub module_id: String,
+    pub use_prelude: bool,
+    pub cxx_flavor: CxxFlavor,
+    // when set, a match none of whose arms applies to the value fails to
+    // compile instead of doing nothing
+    pub exhaustive_match: bool,
+    // how lambdas capture the locals of their enclosing function
+    pub capture: Capture,
+    // path of the esper source, when set #line directives map the C++
+    // back to it so compiler diagnostics can be remapped
+    pub line_directives: Option<String>,
+}
+
+impl Options {
+    pub fn new(module_id: &str) -> Self {
+        Options {
+            module_id: module_id.into(),
+            use_prelude: false,
+            cxx_flavor: CxxFlavor::Clang,
+            exhaustive_match: false,
+            capture: Capture::Reference,
+            line_directives: None,
+        }
+    }
+}
+
+// every syntax error is reported, parsing resumes at the next top-level
+// definition after each of them
+pub fn parse(source: &str) -> Result<Program, Diagnostics> {
+    match parse_program(source) {
+        (root, errors) if errors.is_empty() => Ok(Program {
+            root,
+            source: source.into(),
+            lines: LineIndex::new(source),
+        }),
+
+        (_, errors) => Err(errors
+            .iter()
+            .map(|peg_err| Diagnostic::from_parse_error(source, peg_err))
+            .collect::<Vec<_>>()
+            .into()),
+    }
+}
+
+pub fn emit_cpp(program: &Program, options: Options) -> String {
+    let mut ctx = EmitContextImpl::new();
+    ctx.use_prelude = options.use_prelude;
+    ctx.cxx_flavor = options.cxx_flavor;
+    ctx.exhaustive_match = options.exhaustive_match;
+    ctx.capture = options.capture;
+    ctx.line_map = options
+        .line_directives
+        .map(|path| (path, program.lines.clone()));
+
+    let mut emitter = EmitDefault { ctx };
+    emitter.emit_program(&program.root, &options.module_id)
+}
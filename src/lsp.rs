@@ -0,0 +1,579 @@
+use crate::check::check_program;
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::emit::{EmitContextImpl, EmitDefault};
+use crate::json::Json;
+use crate::parser::{parse_program, Expr, ExprKind};
+use crate::span::{name_span, LineIndex, Span};
+use crate::visit::Visitor;
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+// JSON-RPC error codes from the language server protocol
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+// LSP symbol kinds
+const SYMBOL_FUNCTION: usize = 12;
+const SYMBOL_VARIABLE: usize = 13;
+const SYMBOL_STRUCT: usize = 23;
+
+// a language server over stdio for editors, documents are synced in full
+// and re-parsed on every request since parsing a module is cheap
+pub fn serve(input: impl BufRead, output: impl Write) -> io::Result<i32> {
+    let mut server = Server {
+        input,
+        output,
+        documents: HashMap::new(),
+        shutdown: false,
+    };
+
+    server.run()
+}
+
+struct Server<R, W> {
+    input: R,
+    output: W,
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+}
+
+impl<R: BufRead, W: Write> Server<R, W> {
+    fn run(&mut self) -> io::Result<i32> {
+        while let Some(message) = self.read_message()? {
+            let message = match Json::parse(&message) {
+                Ok(message) => message,
+                Err(_) => continue,
+            };
+
+            let method = message.get("method").as_str().unwrap_or("");
+            let params = message.get("params");
+            let id = message.get("id");
+
+            match method {
+                "initialize" => self.respond(id, initialize_result())?,
+                "initialized" | "$/cancelRequest" | "$/setTrace" => {}
+
+                "shutdown" => {
+                    self.shutdown = true;
+                    self.respond(id, Json::Null)?;
+                }
+
+                "exit" => return Ok(if self.shutdown { 0 } else { 1 }),
+
+                "textDocument/didOpen" => {
+                    let document = params.get("textDocument");
+                    let uri = document.get("uri").as_str().unwrap_or("");
+                    let text = document.get("text").as_str().unwrap_or("");
+
+                    self.update(uri, text)?;
+                }
+
+                // full sync, the last change holds the whole document
+                "textDocument/didChange" => {
+                    let uri = params.get("textDocument").get("uri").as_str().unwrap_or("");
+
+                    if let Json::Array(changes) = params.get("contentChanges") {
+                        if let Some(text) = changes.last().and_then(|c| c.get("text").as_str()) {
+                            self.update(uri, text)?;
+                        }
+                    }
+                }
+
+                "textDocument/didClose" => {
+                    let uri = params.get("textDocument").get("uri").as_str().unwrap_or("");
+
+                    self.documents.remove(uri);
+                    self.publish_diagnostics(uri, vec![])?;
+                }
+
+                "textDocument/documentSymbol"
+                | "textDocument/definition"
+                | "textDocument/hover" => {
+                    let uri = params.get("textDocument").get("uri").as_str().unwrap_or("");
+
+                    let result = match self.documents.get(uri) {
+                        Some(document) => Ok(match method {
+                            "textDocument/documentSymbol" => document.symbols(),
+                            "textDocument/definition" => document.definition(uri, params),
+                            _ => document.hover(params),
+                        }),
+                        None => Err(format!("unknown document {}", uri)),
+                    };
+
+                    match result {
+                        Ok(result) => self.respond(id, result)?,
+                        Err(message) => self.respond_error(id, INVALID_PARAMS, &message)?,
+                    }
+                }
+
+                // notifications without an id are never answered
+                _ if id.is_null() => {}
+                _ => {
+                    self.respond_error(id, METHOD_NOT_FOUND, &format!("unknown method {}", method))?
+                }
+            }
+        }
+
+        // the client went away without asking to exit
+        Ok(1)
+    }
+
+    fn update(&mut self, uri: &str, text: &str) -> io::Result<()> {
+        let document = Document::new(text);
+        let diagnostics = document
+            .diagnostics
+            .iter()
+            .map(|diagnostic| document.lsp_diagnostic(diagnostic))
+            .collect();
+
+        self.documents.insert(uri.into(), document);
+        self.publish_diagnostics(uri, diagnostics)
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str, diagnostics: Vec<Json>) -> io::Result<()> {
+        self.notify(
+            "textDocument/publishDiagnostics",
+            Json::object([("uri", uri.into()), ("diagnostics", diagnostics.into())]),
+        )
+    }
+
+    // messages are framed with a Content-Length header as in HTTP
+    fn read_message(&mut self) -> io::Result<Option<String>> {
+        let mut content_length = None;
+
+        loop {
+            let mut header = String::new();
+
+            if self.input.read_line(&mut header)? == 0 {
+                return Ok(None);
+            }
+
+            let header = header.trim_end();
+
+            if header.is_empty() {
+                break;
+            }
+
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse::<usize>().ok();
+                }
+            }
+        }
+
+        let mut body = vec![0; content_length.unwrap_or(0)];
+        self.input.read_exact(&mut body)?;
+
+        Ok(Some(String::from_utf8_lossy(&body).into_owned()))
+    }
+
+    fn write_message(&mut self, message: Json) -> io::Result<()> {
+        let body = message.to_string();
+
+        write!(
+            self.output,
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )?;
+        self.output.flush()
+    }
+
+    fn respond(&mut self, id: &Json, result: Json) -> io::Result<()> {
+        self.write_message(Json::object([
+            ("jsonrpc", "2.0".into()),
+            ("id", id.clone()),
+            ("result", result),
+        ]))
+    }
+
+    fn respond_error(&mut self, id: &Json, code: i64, message: &str) -> io::Result<()> {
+        let error = Json::object([
+            ("code", Json::Number(code as f64)),
+            ("message", message.into()),
+        ]);
+
+        self.write_message(Json::object([
+            ("jsonrpc", "2.0".into()),
+            ("id", id.clone()),
+            ("error", error),
+        ]))
+    }
+
+    fn notify(&mut self, method: &str, params: Json) -> io::Result<()> {
+        self.write_message(Json::object([
+            ("jsonrpc", "2.0".into()),
+            ("method", method.into()),
+            ("params", params),
+        ]))
+    }
+}
+
+fn initialize_result() -> Json {
+    let capabilities = Json::object([
+        ("textDocumentSync", 1.into()),
+        ("documentSymbolProvider", true.into()),
+        ("definitionProvider", true.into()),
+        ("hoverProvider", true.into()),
+    ]);
+
+    Json::object([
+        ("capabilities", capabilities),
+        (
+            "serverInfo",
+            Json::object([
+                ("name", "esper".into()),
+                ("version", env!("CARGO_PKG_VERSION").into()),
+            ]),
+        ),
+    ])
+}
+
+// an open document along with what was found in its last version, parse
+// recovery keeps the definitions outside of broken items available
+struct Document {
+    text: String,
+    lines: LineIndex,
+    program: Expr,
+    diagnostics: Vec<Diagnostic>,
+    definitions: Vec<Definition>,
+    references: Vec<(String, Span)>,
+}
+
+// a name bound by let, struct, type, a fn parameter or a loop variable,
+// it is visible from anywhere in its scope after the point it was bound
+// (top-level definitions are visible from the whole module)
+#[derive(Debug, Clone)]
+struct Definition {
+    name: String,
+    name_span: Span,
+    span: Span,
+    scope: Span,
+    symbol_kind: Option<usize>,
+    // declaration as the emitted C++ would spell it, shown on hover
+    cpp: String,
+}
+
+impl Document {
+    fn new(text: &str) -> Self {
+        let (program, errors) = parse_program(text);
+
+        let diagnostics = if errors.is_empty() {
+            check_program(&program, text, false)
+        } else {
+            errors
+                .iter()
+                .map(|peg_err| Diagnostic::from_parse_error(text, peg_err))
+                .collect()
+        };
+
+        let (definitions, references) = collect_names(text, &program);
+
+        Document {
+            text: text.into(),
+            lines: LineIndex::new(text),
+            program,
+            diagnostics,
+            definitions,
+            references,
+        }
+    }
+
+    fn position(&self, offset: usize) -> Json {
+        let offset = offset.min(self.text.len());
+        let line = self.lines.line(offset);
+        let line_start = self.lines.line_start(line);
+        let character = self
+            .text
+            .get(line_start..offset)
+            .map_or(0, |prefix| prefix.encode_utf16().count());
+
+        Json::object([("line", (line - 1).into()), ("character", character.into())])
+    }
+
+    fn range(&self, span: Span) -> Json {
+        Json::object([
+            ("start", self.position(span.start)),
+            ("end", self.position(span.end)),
+        ])
+    }
+
+    // positions count UTF-16 code units from the start of a 0-based line
+    fn offset(&self, position: &Json) -> Option<usize> {
+        let line = position.get("line").as_u64()? as usize + 1;
+        let character = position.get("character").as_u64()? as usize;
+
+        if line > self.lines.line_count() {
+            return Some(self.text.len());
+        }
+
+        let line_start = self.lines.line_start(line);
+        let mut units = 0;
+
+        for (offset, c) in self.text[line_start..].char_indices() {
+            if units >= character || c == '\n' {
+                return Some(line_start + offset);
+            }
+
+            units += c.len_utf16();
+        }
+
+        Some(self.text.len())
+    }
+
+    fn lsp_diagnostic(&self, diagnostic: &Diagnostic) -> Json {
+        let severity = match diagnostic.severity {
+            Severity::Error => 1,
+            Severity::Warning => 2,
+        };
+
+        let mut message = diagnostic.message.clone();
+
+        for note in &diagnostic.notes {
+            message.push_str("\nnote: ");
+            message.push_str(note);
+        }
+
+        Json::object([
+            ("range", self.range(diagnostic.span)),
+            ("severity", Json::Number(severity as f64)),
+            ("code", diagnostic.code.clone().into()),
+            ("source", "esper".into()),
+            ("message", message.into()),
+        ])
+    }
+
+    fn symbols(&self) -> Json {
+        let symbols = self
+            .definitions
+            .iter()
+            .filter_map(|def| {
+                let kind = def.symbol_kind?;
+
+                Some(Json::object([
+                    ("name", def.name.clone().into()),
+                    ("detail", def.cpp.clone().into()),
+                    ("kind", kind.into()),
+                    ("range", self.range(def.span)),
+                    ("selectionRange", self.range(def.name_span)),
+                ]))
+            })
+            .collect::<Vec<_>>();
+
+        symbols.into()
+    }
+
+    fn definition(&self, uri: &str, params: &Json) -> Json {
+        match self.definition_at(params) {
+            Some(def) => Json::object([("uri", uri.into()), ("range", self.range(def.name_span))]),
+            None => Json::Null,
+        }
+    }
+
+    fn hover(&self, params: &Json) -> Json {
+        match self.definition_at(params) {
+            Some(def) if !def.cpp.is_empty() => Json::object([
+                (
+                    "contents",
+                    Json::object([
+                        ("kind", "markdown".into()),
+                        ("value", format!("```cpp\n{}\n```", def.cpp).into()),
+                    ]),
+                ),
+                ("range", self.range(def.name_span)),
+            ]),
+            _ => Json::Null,
+        }
+    }
+
+    // the definition named at a position, either by a reference to it or
+    // by the name in the definition itself
+    fn definition_at(&self, params: &Json) -> Option<&Definition> {
+        let offset = self.offset(params.get("position"))?;
+        let at = |span: Span| span.contains(offset) || span.end == offset;
+
+        if let Some(def) = self.definitions.iter().find(|def| at(def.name_span)) {
+            return Some(def);
+        }
+
+        let (name, span) = self
+            .references
+            .iter()
+            .filter(|(_, span)| at(*span))
+            .min_by_key(|(_, span)| span.len())?;
+
+        self.resolve(name, span.start)
+    }
+
+    // the innermost scope wins, among definitions in the same scope the
+    // last one bound before the reference shadows the others
+    fn resolve(&self, name: &str, offset: usize) -> Option<&Definition> {
+        let program_scope = self.program.span;
+
+        self.definitions
+            .iter()
+            .filter(|def| def.name == name)
+            .filter(|def| def.scope.contains(offset) || def.scope == program_scope)
+            .filter(|def| def.span.start <= offset || def.scope == program_scope)
+            .min_by_key(|def| (def.scope.len(), offset.abs_diff(def.span.start)))
+    }
+}
+
+// walks the program with the Visitor trait, references are the variables
+// and type names that can be resolved to a definition
+fn collect_names(text: &str, program: &Expr) -> (Vec<Definition>, Vec<(String, Span)>) {
+    let emitter = EmitDefault {
+        ctx: EmitContextImpl::new(),
+    };
+
+    let mut ctx = EmitContextImpl::new();
+    let mut definitions = vec![];
+    let mut references = vec![];
+    let mut scopes = vec![program.span];
+
+    program.visit(&mut ctx, &mut |_, expr| {
+        let mut define = |name: &str, scope: Option<Span>, symbol_kind, cpp: String| {
+            definitions.push(Definition {
+                name: name.into(),
+                name_span: name_span(text, expr.span, name),
+                span: expr.span,
+                scope: scope.unwrap_or_default(),
+                symbol_kind,
+                cpp,
+            })
+        };
+
+        let template = |ty_params: &[Expr]| {
+            if ty_params.is_empty() {
+                String::new()
+            } else {
+                format!("template<{}>\n", emitter.emit_template_params(ty_params))
+            }
+        };
+
+        // typed and untyped functions share the same parameter list
+        let signature = |params: &[(String, Option<Expr>)]| {
+            params
+                .iter()
+                .map(|(param, ty)| match ty {
+                    Some(ty) => format!("{} {}", emitter.emit_type(ty), param),
+                    None => format!("auto {}", param),
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        match &expr.kind {
+            ExprKind::Let(name, ty_params, value) => match &value.kind {
+                ExprKind::Fn(params, _) => define(
+                    name,
+                    None,
+                    Some(SYMBOL_FUNCTION),
+                    format!(
+                        "{}auto {}({})",
+                        template(ty_params),
+                        name,
+                        signature(params)
+                    ),
+                ),
+
+                _ => define(name, None, Some(SYMBOL_VARIABLE), format!("auto {}", name)),
+            },
+
+            ExprKind::TypedLet(name, ty_params, ty, value) => match &value.kind {
+                ExprKind::Fn(params, _) => define(
+                    name,
+                    None,
+                    Some(SYMBOL_FUNCTION),
+                    format!(
+                        "{}{} {}({})",
+                        template(ty_params),
+                        emitter.emit_type(ty),
+                        name,
+                        signature(params)
+                    ),
+                ),
+
+                _ => define(
+                    name,
+                    None,
+                    Some(SYMBOL_VARIABLE),
+                    format!("{}{} {}", template(ty_params), emitter.emit_type(ty), name),
+                ),
+            },
+
+            ExprKind::Struct(name, ty_params, _) => define(
+                name,
+                None,
+                Some(SYMBOL_STRUCT),
+                format!("{}struct {}", template(ty_params), name),
+            ),
+
+            ExprKind::TypeAlias(name, ty_params, ty) => {
+                define(
+                    name,
+                    None,
+                    Some(SYMBOL_STRUCT),
+                    format!(
+                        "{}using {} = {}",
+                        template(ty_params),
+                        name,
+                        emitter.emit_type(ty)
+                    ),
+                );
+            }
+
+            // parameters are scoped to the fn, their type is only known
+            // from their annotation
+            ExprKind::Fn(params, _) => {
+                scopes.push(expr.span);
+
+                for (param, ty) in params {
+                    let cpp = match ty {
+                        Some(ty) => format!("{} {}", emitter.emit_type(ty), param),
+                        None => format!("auto {}", param),
+                    };
+
+                    define(param, Some(expr.span), None, cpp);
+                }
+            }
+
+            ExprKind::Loop(var, _, _) => {
+                scopes.push(expr.span);
+
+                if let ExprKind::Var(name) = &var.kind {
+                    definitions.push(Definition {
+                        name: name.clone(),
+                        name_span: var.span,
+                        span: var.span,
+                        scope: expr.span,
+                        symbol_kind: None,
+                        cpp: format!("auto {}", name),
+                    });
+                }
+            }
+
+            ExprKind::Var(name) | ExprKind::TypedSymbol(name) => {
+                references.push((name.clone(), expr.span))
+            }
+
+            _ => {}
+        }
+    });
+
+    // let, struct and type are scoped to the innermost fn or loop around them
+    for def in definitions.iter_mut() {
+        if def.scope == Span::default() {
+            def.scope = scopes
+                .iter()
+                .filter(|scope| scope.start <= def.span.start && def.span.end <= scope.end)
+                .filter(|scope| **scope != def.span)
+                .min_by_key(|scope| scope.len())
+                .copied()
+                .unwrap_or(program.span);
+        }
+    }
+
+    (definitions, references)
+}
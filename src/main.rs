@@ -1,35 +1,427 @@
 #![allow(warnings, dead_code)]
+use esper::diagnostics::{render_message, MessageFormat};
+use esper::format::format_source;
+use esper::{Capture, CompileError, CxxCompiler, Session};
+use std::env;
+use std::fs;
+use std::io;
 use std::path::PathBuf;
-use std::process::exit;
+use std::process::{self, exit, Command};
 use structopt::StructOpt;
 
-mod cc;
-mod emit;
-mod parser;
-mod visit;
+#[derive(StructOpt)]
+#[structopt(name = "esper")]
+enum Opt {
+    /// Compiles a module to an executable (or to C++ source with --emit)
+    Build {
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
 
-use crate::cc::*;
-use crate::parser::esper_parser;
+        #[structopt(short, long, parse(from_os_str))]
+        output: PathBuf,
 
-#[derive(StructOpt)]
-struct Opt {
-    #[structopt(parse(from_os_str))]
-    input: PathBuf,
+        #[structopt(short, long)]
+        prelude: bool,
+
+        #[structopt(short, long)]
+        emit: bool,
+
+        /// How lambdas capture local variables, by reference or by value
+        #[structopt(long, default_value = "reference", possible_values = &["reference", "value"])]
+        capture: Capture,
+
+        /// Fails to compile a match none of whose arms applies to a value
+        #[structopt(long)]
+        exhaustive_match: bool,
+
+        /// Leaves names the module does not define to the C++ compiler
+        #[structopt(long)]
+        allow_undefined: bool,
+
+        /// Skips name resolution and type checking, only syntax errors are reported
+        #[structopt(long)]
+        no_check: bool,
+
+        /// C++ compiler to use, defaults to $CXX or clang++
+        #[structopt(long)]
+        cxx: Option<String>,
+
+        #[structopt(last = true)]
+        clang_flags: Vec<String>,
+
+        /// Diagnostic output format, human or json (one object per line)
+        #[structopt(long, default_value = "human", possible_values = &["human", "json"])]
+        message_format: MessageFormat,
+    },
+
+    /// Compiles a module into a temporary directory and runs it
+    Run {
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        #[structopt(short, long)]
+        prelude: bool,
+
+        /// How lambdas capture local variables, by reference or by value
+        #[structopt(long, default_value = "reference", possible_values = &["reference", "value"])]
+        capture: Capture,
+
+        /// Fails to compile a match none of whose arms applies to a value
+        #[structopt(long)]
+        exhaustive_match: bool,
+
+        /// Leaves names the module does not define to the C++ compiler
+        #[structopt(long)]
+        allow_undefined: bool,
+
+        /// C++ compiler to use, defaults to $CXX or clang++
+        #[structopt(long)]
+        cxx: Option<String>,
+
+        /// Raw argument passed to the C++ compiler (repeatable)
+        #[structopt(
+            short = "X",
+            long = "cxx-flag",
+            allow_hyphen_values = true,
+            number_of_values = 1
+        )]
+        clang_flags: Vec<String>,
+
+        /// Diagnostic output format, human or json (one object per line)
+        #[structopt(long, default_value = "human", possible_values = &["human", "json"])]
+        message_format: MessageFormat,
+
+        /// Arguments passed to the program
+        #[structopt(last = true)]
+        args: Vec<String>,
+    },
 
-    #[structopt(short, long, parse(from_os_str))]
-    output: PathBuf,
+    /// Runs a module with the interpreter, without a C++ compiler
+    Interp {
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
 
-    #[structopt(short, long)]
-    prelude: bool,
+        /// How lambdas capture local variables, by reference or by value
+        #[structopt(long, default_value = "reference", possible_values = &["reference", "value"])]
+        capture: Capture,
+
+        /// Diagnostic output format, human or json (one object per line)
+        #[structopt(long, default_value = "human", possible_values = &["human", "json"])]
+        message_format: MessageFormat,
+
+        /// Arguments passed to the program
+        #[structopt(last = true)]
+        args: Vec<String>,
+    },
+
+    /// Parses and validates modules without invoking a C++ compiler
+    Check {
+        #[structopt(parse(from_os_str), required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Leaves names the module does not define to the C++ compiler
+        #[structopt(long)]
+        allow_undefined: bool,
+
+        /// Diagnostic output format, human or json (one object per line)
+        #[structopt(long, default_value = "human", possible_values = &["human", "json"])]
+        message_format: MessageFormat,
+    },
+
+    /// Rewrites modules in the canonical style
+    Fmt {
+        #[structopt(parse(from_os_str), required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Fails instead of writing when an input is not formatted
+        #[structopt(long)]
+        check: bool,
+    },
+
+    /// Runs a language server over stdio
+    Lsp,
+
+    /// Evaluates expressions and definitions interactively
+    Repl {
+        /// C++ compiler to use, defaults to $CXX or clang++
+        #[structopt(long)]
+        cxx: Option<String>,
+
+        /// Raw argument passed to the C++ compiler (repeatable)
+        #[structopt(
+            short = "X",
+            long = "cxx-flag",
+            allow_hyphen_values = true,
+            number_of_values = 1
+        )]
+        clang_flags: Vec<String>,
+    },
+}
+
+// a session writing its messages to stderr once the build is done
+fn session(cxx: CxxCompiler, message_format: MessageFormat) -> Session {
+    let mut session = Session::new(cxx);
+    session.message_format = message_format;
+    session
+}
+
+fn print_messages(session: &mut Session) {
+    for message in session.take_messages() {
+        eprintln!("{}", message);
+    }
+}
+
+fn build(
+    input_path: PathBuf,
+    output_path: PathBuf,
+    mut session: Session,
+    should_emit: bool,
+) -> Result<(), CompileError> {
+    let id = session.add_file(&input_path)?;
+
+    let result = if should_emit {
+        session.emit_cpp(id, false).and_then(|cxx_source| {
+            fs::write(&output_path, cxx_source)
+                .map_err(|err| CompileError::Io(output_path.clone(), err))
+        })
+    } else {
+        session.build(id, &output_path)
+    };
+
+    print_messages(&mut session);
+    result
+}
 
-    #[structopt(short, long)]
-    emit: bool,
+fn interp(
+    input_path: PathBuf,
+    mut session: Session,
+    args: Vec<String>,
+) -> Result<i32, CompileError> {
+    let id = session.add_file(&input_path)?;
+    let result = session.interpret(id, args, io::stdout());
 
-    #[structopt(last = true)]
-    clang_flags: Vec<String>,
+    print_messages(&mut session);
+    result
+}
+
+// parses and runs the semantic passes over every input without emitting
+// or compiling, all diagnostics are reported before failing
+fn check(input_paths: Vec<PathBuf>, mut session: Session) -> Result<(), CompileError> {
+    let mut parse_failed = false;
+
+    for input_path in &input_paths {
+        let id = session.add_file(input_path)?;
+
+        if let Err(CompileError::Parse(_)) = session.check(id) {
+            parse_failed = true;
+        }
+
+        print_messages(&mut session);
+    }
+
+    match session.error_count() {
+        0 => Ok(()),
+        _ if parse_failed => Err(CompileError::Parse(session.summary())),
+        _ => Err(CompileError::Semantic(session.summary())),
+    }
+}
+
+// rewrites every input in the canonical style, with --check the inputs
+// are left untouched and the command fails when one of them would change
+fn fmt(input_paths: Vec<PathBuf>, check: bool, mut session: Session) -> Result<(), CompileError> {
+    let mut parse_failed = false;
+    let mut unformatted = vec![];
+
+    for input_path in input_paths {
+        let id = session.add_file(&input_path)?;
+
+        match format_source(session.source(id)) {
+            Ok(formatted) if formatted == session.source(id) => {}
+            Ok(_) if check => unformatted.push(input_path),
+
+            Ok(formatted) => fs::write(&input_path, formatted)
+                .map_err(|err| CompileError::Io(input_path.clone(), err))?,
+
+            Err(diagnostics) => {
+                for diagnostic in diagnostics {
+                    session.report(id, &diagnostic);
+                }
+
+                parse_failed = true;
+            }
+        }
+
+        print_messages(&mut session);
+    }
+
+    if parse_failed {
+        Err(CompileError::Parse(session.summary()))
+    } else if !unformatted.is_empty() {
+        Err(CompileError::Unformatted(unformatted))
+    } else {
+        Ok(())
+    }
+}
+
+// builds into a fresh temporary directory, runs the executable with the
+// given arguments and returns its exit status, the directory is removed
+// whether or not the program succeeded
+fn run(input_path: PathBuf, session: Session, args: Vec<String>) -> Result<i32, CompileError> {
+    let module_id = input_path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("main")
+        .to_string();
+    let build_dir = env::temp_dir().join(format!("esper-{}-{}", process::id(), module_id));
+
+    fs::create_dir_all(&build_dir).map_err(|err| CompileError::Io(build_dir.clone(), err))?;
+
+    let exe_path = build_dir.join(&module_id);
+    let status = build(input_path, exe_path.clone(), session, false).and_then(|_| {
+        Command::new(&exe_path)
+            .args(args)
+            .status()
+            .map_err(|err| CompileError::Io(exe_path.clone(), err))
+    });
+
+    let _ = fs::remove_dir_all(&build_dir);
+
+    // terminated by a signal, follow the shell convention where possible
+    Ok(status?.code().unwrap_or(1))
 }
 
 fn main() {
-    let args = Opt::from_args();
-    compile(args.input, args.output, args.clang_flags, args.prelude, args.emit);
+    let (result, message_format) = match Opt::from_args() {
+        Opt::Build {
+            input,
+            output,
+            prelude,
+            emit,
+            exhaustive_match,
+            allow_undefined,
+            no_check,
+            capture,
+            cxx,
+            clang_flags,
+            message_format,
+        } => {
+            let mut session = session(CxxCompiler::resolve(cxx), message_format);
+            session.cxx_flags = clang_flags;
+            session.use_prelude = prelude;
+            session.exhaustive_match = exhaustive_match;
+            session.skip_checks = no_check;
+            session.allow_undefined = allow_undefined;
+            session.capture = capture;
+
+            (
+                build(input, output, session, emit).map(|_| 0),
+                message_format,
+            )
+        }
+
+        Opt::Run {
+            input,
+            prelude,
+            exhaustive_match,
+            allow_undefined,
+            capture,
+            cxx,
+            clang_flags,
+            message_format,
+            args,
+        } => {
+            let mut session = session(CxxCompiler::resolve(cxx), message_format);
+            session.cxx_flags = clang_flags;
+            session.use_prelude = prelude;
+            session.exhaustive_match = exhaustive_match;
+            session.allow_undefined = allow_undefined;
+            session.capture = capture;
+
+            (run(input, session, args), message_format)
+        }
+
+        Opt::Interp {
+            input,
+            capture,
+            message_format,
+            args,
+        } => {
+            // the interpreter never needs the C++ compiler
+            let mut session = session(CxxCompiler::new("clang++"), message_format);
+            session.capture = capture;
+
+            (interp(input, session, args), message_format)
+        }
+
+        Opt::Check {
+            inputs,
+            allow_undefined,
+            message_format,
+        } => {
+            // semantic passes never need the C++ compiler
+            let mut session = session(CxxCompiler::new("clang++"), message_format);
+            session.allow_undefined = allow_undefined;
+
+            (check(inputs, session).map(|_| 0), message_format)
+        }
+
+        Opt::Fmt { inputs, check } => {
+            let session = session(CxxCompiler::new("clang++"), MessageFormat::Human);
+
+            (fmt(inputs, check, session).map(|_| 0), MessageFormat::Human)
+        }
+
+        Opt::Lsp => (
+            esper::lsp::serve(io::stdin().lock(), io::stdout().lock())
+                .map_err(|err| CompileError::Io("<stdio>".into(), err)),
+            MessageFormat::Human,
+        ),
+
+        Opt::Repl { cxx, clang_flags } => {
+            let mut session = session(CxxCompiler::resolve(cxx), MessageFormat::Human);
+            session.cxx_flags = clang_flags;
+
+            (
+                esper::repl::run(io::stdin().lock(), io::stdout().lock(), session)
+                    .map(|_| 0)
+                    .map_err(|err| CompileError::Io("<stdio>".into(), err)),
+                MessageFormat::Human,
+            )
+        }
+    };
+
+    match result {
+        Ok(code) => exit(code),
+        Err(err) => {
+            match (&err, message_format) {
+                // diagnostics are already rendered in the requested format
+                (_, MessageFormat::Human)
+                | (
+                    CompileError::Parse(_)
+                    | CompileError::Semantic(_)
+                    | CompileError::Runtime(_)
+                    | CompileError::Unformatted(_),
+                    _,
+                ) => {
+                    eprintln!("{}", err)
+                }
+
+                (CompileError::Io(path, io_err), _) => eprintln!(
+                    "{}",
+                    render_message(
+                        message_format,
+                        "error",
+                        Some(&path.to_string_lossy()),
+                        &io_err.to_string(),
+                    )
+                ),
+
+                (CompileError::Backend(msg), _) => {
+                    eprintln!("{}", render_message(message_format, "error", None, msg))
+                }
+            }
+
+            exit(err.exit_code());
+        }
+    }
 }
@@ -1,7 +1,15 @@
+use crate::span::Span;
 use peg::parser;
 
 parser! {
-  pub grammar esper_parser() for str {
+  pub grammar esper_parser(offset: usize) for str {
+    // wraps a rule producing an expression kind into a node spanning
+    // every byte consumed by that rule
+    rule spanned(kind: rule<ExprKind>) -> Expr
+      = start:position!() kind:kind() end:position!() {
+      Expr::new(kind, Span::new(offset + start, offset + end))
+    }
+
     rule typed_primary() -> Expr
       = typed_literal() / typed_member() / typed_symbol_generic() /
         typed_symbol() / type_optional() / typed_record() / typed_variant() / typed_fn_expr()
@@ -10,11 +18,11 @@ parser! {
       =  typed_unary() / typed_primary()
 
     rule typed_literal() -> Expr
-      = ty:(integer_literal() / float_literal() / bool_literal())
-        { Expr::TypedLiteral(ty.into()) }
+      = spanned(<ty:(integer_literal() / float_literal() / bool_literal())
+        { ExprKind::TypedLiteral(ty.into()) }>)
 
     rule typed_symbol() -> Expr
-      = id:identifier() { Expr::TypedSymbol(id.into()) }
+      = spanned(<id:identifier() { ExprKind::TypedSymbol(id.into()) }>)
 
     rule typed_unary_op() -> UnaryOp
     = op:$("&" / "*") {
@@ -26,10 +34,15 @@ parser! {
     }
 
     rule typed_unary() -> Expr
-    = ops:typed_unary_op()** _ expr:typed_primary() {
+    = start:position!() ops:typed_unary_op()** _ expr:typed_primary() end:position!() {
+      let span = Span::new(offset + start, offset + end);
+
       ops.into_iter().rev().fold(expr, |acc, op| {
-        Expr::TypedUnary(
-          Box::new(Expr::Unary(Box::new(acc), op))
+        Expr::new(
+          ExprKind::TypedUnary(
+            Box::new(Expr::new(ExprKind::Unary(Box::new(acc), op), span))
+          ),
+          span,
         )
       })
     }
@@ -38,16 +51,22 @@ parser! {
     //   = e:unary() { Expr::TypedUnary(Box::new(e)) }
 
     rule type_optional() -> Expr
-      = "?" _ ty:typed_expr() {
-      Expr::TypedOptional(Box::new(ty))
-    }
+      = spanned(<"?" _ ty:typed_expr() {
+      ExprKind::TypedOptional(Box::new(ty))
+    }>)
 
     rule typed_fn_expr() -> Expr
-    = "|" _ args:(fn_arg() ** (_ "," _)) _ "|" _ ty:typed_expr() _ "end" {
-      Expr::TypedFn(
-        Box::new(
-          Expr::Fn(args, vec![ty])
-        )
+    = start:position!() "|" _ args:(fn_arg() ** (_ "," _)) _ "|" _ ty:typed_expr() _ "end"
+      end:position!() {
+      let span = Span::new(offset + start, offset + end);
+
+      Expr::new(
+        ExprKind::TypedFn(
+          Box::new(
+            Expr::new(ExprKind::Fn(args, vec![ty]), span)
+          )
+        ),
+        span,
       )
     }
 
@@ -69,26 +88,30 @@ parser! {
       = key:typed_record_key() _ ":" _ value:typed_expr() { (key, value) }
 
     rule typed_record() -> Expr
-      = "{" _ entries:(typed_record_entry() ** (_ "," _)) _ "}" {
+      = start:position!() "{" _ entries:(typed_record_entry() ** (_ "," _)) _ "}" end:position!() {
       let kv_pairs = entries.into_iter().map(|(key, value)| vec![key, value]).collect();
+      let span = Span::new(offset + start, offset + end);
 
-      Expr::TypedRecord(Box::new(Expr::Record(kv_pairs)))
+      Expr::new(
+        ExprKind::TypedRecord(Box::new(Expr::new(ExprKind::Record(kv_pairs), span))),
+        span,
+      )
     }
 
     rule typed_member() -> Expr
-      = expr:member_expr() { Expr::TypedMember(Box::new(expr)) }
+      = spanned(<expr:member_expr() { ExprKind::TypedMember(Box::new(expr)) }>)
 
     rule typed_symbol_generic() -> Expr
-      = id:identifier() _ ty:type_generic()
-      { Expr::TypedSymbolGeneric(id.into(), ty) }
+      = spanned(<id:identifier() _ ty:type_generic()
+      { ExprKind::TypedSymbolGeneric(id.into(), ty) }>)
 
     rule type_alias() -> Expr
-    = "type" _ id:identifier() _ "=" _ type_expr:typed_expr() _ "end" {
-        Expr::TypeAlias(id.into(), vec![], Box::new(type_expr))
+    = spanned(<"type" _ id:identifier() _ "=" _ type_expr:typed_expr() _ "end" {
+        ExprKind::TypeAlias(id.into(), vec![], Box::new(type_expr))
       }
     / "type" _ id:identifier() _ ty:type_generic() _ "=" _ type_expr:typed_expr() _ "end" {
-        Expr::TypeAlias(id.into(), ty, Box::new(type_expr))
-      }
+        ExprKind::TypeAlias(id.into(), ty, Box::new(type_expr))
+      }>)
 
     rule type_generic() -> Vec<Expr>
       = "<" _ args:(typed_expr() ** (_ "," _)) _ ">" {
@@ -100,7 +123,8 @@ parser! {
         let mut expr = first;
 
         for variant in rest {
-            expr = Expr::TypedVariant(Box::new(expr), Box::new(variant));
+            let span = expr.span.to(variant.span);
+            expr = Expr::new(ExprKind::TypedVariant(Box::new(expr), Box::new(variant)), span);
         }
 
         expr
@@ -115,87 +139,97 @@ parser! {
     rule comment() = "(*" (!"*)" [_])* "*)"
 
     rule pass() -> Expr
-      = "pass" { Expr::Pass }
+      = spanned(<"pass" { ExprKind::Pass }>)
 
     rule identifier() -> &'input str
       = quiet!{s:$(['a'..='z' | 'A'..='Z' | '_']+)} / expected!("identifier")
       // { s.into() }
 
       rule float_literal() -> Expr
-      = sign:("-")? n:$(['0'..='9']+ "." ['0'..='9']*) {
+      = spanned(<sign:("-")? n:$(['0'..='9']+ "." ['0'..='9']*) {
           let mut num = n.parse::<f64>().unwrap();
 
           if sign.is_some() {
               num = -num;
           }
 
-          Expr::Float(num)
-      }
+          ExprKind::Float(num)
+      }>)
 
     rule integer_literal() -> Expr
-      = sign:("-")? n:$(['0'..='9']+) {
+      = spanned(<sign:("-")? n:$(['0'..='9']+) {
           let mut num = n.parse::<i64>().unwrap();
 
           if sign.is_some() {
               num = -num;
           }
 
-          Expr::Int(num)
-      }
+          ExprKind::Int(num)
+      }>)
 
     rule bool_literal() -> Expr
-      = "true" { Expr::Bool(true) }
-      / "false" { Expr::Bool(false) }
+      = spanned(<"true" { ExprKind::Bool(true) }
+      / "false" { ExprKind::Bool(false) }>)
 
     rule string_literal() -> Expr
-      = "\"" value:$([^ '"' ]*) "\"" {
-      Expr::String(value.into())
-    }
+      = spanned(<"\"" value:$([^ '"' ]*) "\"" {
+      ExprKind::String(value.into())
+    }>)
 
     rule char_literal() -> Expr
-      = "'" value:$([^ '\'' ]) "'" {
-      Expr::Char(value.chars().next().unwrap())
-    }
+      = spanned(<"'" value:$([^ '\'' ]) "'" {
+      ExprKind::Char(value.chars().next().unwrap())
+    }>)
 
     rule directive_expr() -> Expr
-      = "@" _ directive:(call_expr() / identifier_expr()) _ expr:primary() {
-      Expr::Directive(Box::new(directive), Box::new(expr))
-    }
+      = spanned(<"@" _ directive:(call_expr() / identifier_expr()) _ expr:primary() {
+      ExprKind::Directive(Box::new(directive), Box::new(expr))
+    }>)
 
     rule range_expr() -> Expr
-      = start:(integer_literal() / identifier_expr())
+      = spanned(<start:(integer_literal() / identifier_expr())
         ".."
         end:(integer_literal() / identifier_expr()) {
-          Expr::Range(Box::new(start), Box::new(end))
-      }
+          ExprKind::Range(Box::new(start), Box::new(end))
+      }>)
 
     rule member_expr() -> Expr
-    =
+    = spanned(<
     base:identifier_expr() (_ "." _) rest:(member_expr() / call_expr())* {
         let mut members = vec![base];
         members.extend(rest);
-        Expr::Member(members)
-    }
+        ExprKind::Member(members)
+    }>)
 
     rule call_expr() -> Expr
-    = callee:(fn_expr() / identifier_expr() / member_expr()) "(" _? args:(expr() ** (_ "," _)) _? ")" {
-        Expr::Call(Box::new(callee), args)
+    = spanned(<callee:(fn_expr() / identifier_expr() / member_expr()) "(" _? args:(expr() ** (_ "," _)) _? ")" {
+        ExprKind::Call(Box::new(callee), args)
       }
     / callee:(identifier_expr() / member_expr()) _ ty:type_generic() _ "(" _? args:(expr() ** (_ "," _)) _? ")" {
-      Expr::TypedCall(Box::new(callee), ty, args)
-    }
+      ExprKind::TypedCall(Box::new(callee), ty, args)
+    }>)
 
     rule let_binding() -> Expr
-      = "let" _ id:identifier() _ "=" _ expr:expr() { Expr::Let(id.into(), Box::new(expr)) }
+      = spanned(<"let" _ id:identifier() _ "=" _ expr:expr() {
+        ExprKind::Let(id.into(), vec![], Box::new(expr))
+      }
       / "let" _ id:identifier() _ ":" _ ty:typed_expr() _ "=" _ expr:expr() {
-        Expr::TypedLet(id.into(), Box::new(ty), Box::new(expr))
+        ExprKind::TypedLet(id.into(), vec![], Box::new(ty), Box::new(expr))
+      }
+      // only functions take type parameters
+      / "let" _ id:identifier() _ params:type_generic() _ "=" _ function:fn_expr() {
+        ExprKind::Let(id.into(), params, Box::new(function))
       }
+      / "let" _ id:identifier() _ params:type_generic() _ ":" _ ty:typed_expr() _ "=" _
+        function:fn_expr() {
+        ExprKind::TypedLet(id.into(), params, Box::new(ty), Box::new(function))
+      }>)
 
     rule assignable() -> Expr
       = member_expr() / identifier_expr()
 
     rule assign() -> Expr
-      = lhs:assignable() _ "=" _ rhs:expr() { Expr::Assign(Box::new(lhs), Box::new(rhs)) }
+      = spanned(<lhs:assignable() _ "=" _ rhs:expr() { ExprKind::Assign(Box::new(lhs), Box::new(rhs)) }>)
 
     // assign must hold the highest precedence
     rule primary() -> Expr
@@ -203,19 +237,19 @@ parser! {
         type_alias() / call_expr() / range_expr() / member_expr() /
         loop_expr() / if_expr() / fn_expr() / let_binding() / pass() / bool_literal() /
         float_literal() / integer_literal() / string_literal() / char_literal() /
-        identifier_expr() / list() / record()
+        construct() / identifier_expr() / list() / record()
 
     rule expr() -> Expr
-      = add_sub() / unary() / bit() / compare() / primary()
+      = operators()
 
     rule paren_expr() -> Expr
       = "(" _ e:expr() _ ")" { e }
 
     rule identifier_expr() -> Expr
-      = id:identifier() { Expr::Var(id.into()) }
+      = spanned(<id:identifier() { ExprKind::Var(id.into()) }>)
 
     rule list() -> Expr
-      = "[" lst:(expr() ** (_ "," _)) "]" { Expr::List(lst) }
+      = spanned(<"[" lst:(expr() ** (_ "," _)) "]" { ExprKind::List(lst) }>)
 
     rule record_key() -> Expr
       = identifier_expr() / integer_literal()
@@ -224,104 +258,156 @@ parser! {
       = key:record_key() _ ":" _ value:expr() { (key, value) }
 
     rule record() -> Expr
-      = "{" _ entries:(record_entry() ** (_ "," _)) _ "}" {
+      = spanned(<"{" _ entries:(record_entry() ** (_ "," _)) _ "}" {
       let kv_pairs = entries.into_iter().map(|(key, value)| vec![key, value]).collect();
 
-      Expr::Record(kv_pairs)
-    }
+      ExprKind::Record(kv_pairs)
+    }>)
+
+    // a struct initialized by field, `B { c: 1 }`
+    rule construct() -> Expr
+      = spanned(<id:identifier() quiet!{[' ' | '\t']*} fields:record() {
+      ExprKind::Construct(id.into(), Box::new(fields))
+    }>)
 
     rule struct_expr() -> Expr
-      = "struct" _ id:identifier() _ entries:(struct_entry() ** (_ "," _)) _ "end" {
+      = spanned(<"struct" _ id:identifier() _ params:type_generic()? _
+        entries:(struct_entry() ** (_ "," _)) _ "end" {
       let entries = entries.into_iter().collect();
-      Expr::Struct(id.into(), entries)
-    }
+      ExprKind::Struct(id.into(), params.unwrap_or_default(), entries)
+    }>)
 
+    // methods may declare their return type as typed definitions do
     rule struct_entry() -> (String, Expr)
-    = prop:identifier() _ ":" _ type_:typed_expr() {
+    = start:position!() method:identifier() _ ":" _ ty:typed_expr() _ "=" _ fn_:fn_expr() end:position!() {
+      let kind = ExprKind::TypedLet(method.into(), vec![], Box::new(ty), Box::new(fn_));
+      (method.into(), Expr::new(kind, Span::new(offset + start, offset + end)))
+    }
+    / prop:identifier() _ ":" _ type_:typed_expr() &(_ ("," / "end")) {
       (prop.into(), type_)
     }
     / method:identifier() _ ":" _ fn_:fn_expr() {
       (method.into(), fn_)
     }
 
-    rule add_sub() -> Expr
-      = lhs:mul_div() _ op:$("+" / "-") _ rhs:mul_div() {
-        let op_enum = match op {
-          "+" => BinOp::Add,
-          "-" => BinOp::Sub,
-          _ => unreachable!(),
-        };
-
-        Expr::Bin(Box::new(lhs), op_enum, Box::new(rhs))
-    } / mul_div()
-
-    rule mul_div() -> Expr
-      = lhs:primary() _ op:$("*" / "/") _ rhs:primary() {
+    // binary and prefix operators, from the loosest to the tightest binding
+    // level, every binary level is left-associative (`a - b - c` is `(a - b) - c`)
+    //
+    //   or
+    //   and
+    //   eq neq
+    //   gt lt gte lte
+    //   bor
+    //   xor
+    //   band
+    //   shl shr rotl rotr
+    //   + -
+    //   * /
+    //   ~ & * (prefix)
+    rule operators() -> Expr = precedence!{
+      start:position!() expr:@ end:position!() {
+        let expr: Expr = expr;
+        Expr::new(expr.kind, Span::new(offset + start, offset + end))
+      }
+      --
+      lhs:(@) _ "or" !ident_char() _ rhs:@ {
+        ExprKind::Compare(Box::new(lhs), CompareOp::Or, Box::new(rhs)).into()
+      }
+      --
+      lhs:(@) _ "and" !ident_char() _ rhs:@ {
+        ExprKind::Compare(Box::new(lhs), CompareOp::And, Box::new(rhs)).into()
+      }
+      --
+      lhs:(@) _ op:$("eq" / "neq") !ident_char() _ rhs:@ {
         let op_enum = match op {
-          "*" => BinOp::Mul,
-          "/" => BinOp::Div,
+          "eq" => CompareOp::Eq,
+          "neq" => CompareOp::Neq,
           _ => unreachable!(),
         };
 
-        Expr::Bin(Box::new(lhs), op_enum, Box::new(rhs))
-    } / bit() / compare()
-
-    rule compare_op() -> &'input str
-      = op:$("gte" / "lte" / "gt" / "lt" / "eq" / "neq" / "and" / "or") { op }
-
-    rule compare() -> Expr
-      = lhs:primary() _ op:compare_op() _ rhs:primary() {
+        ExprKind::Compare(Box::new(lhs), op_enum, Box::new(rhs)).into()
+      }
+      --
+      lhs:(@) _ op:$("gte" / "lte" / "gt" / "lt") !ident_char() _ rhs:@ {
         let op_enum = match op {
           "gt" => CompareOp::Gt,
           "lt" => CompareOp::Lt,
           "gte" => CompareOp::Gte,
           "lte" => CompareOp::Lte,
-          "eq" => CompareOp::Eq,
-          "neq" => CompareOp::Neq,
-          "and" => CompareOp::And,
-          "or" => CompareOp::Or,
           _ => unreachable!(),
         };
 
-        Expr::Compare(Box::new(lhs), op_enum, Box::new(rhs))
-    } / primary()
-
-    rule bit_op() -> &'input str
-      = op:$("shl" / "shr" / "band" / "bor" / "xor" / "rotl" / "rotr") { op }
-
-    rule bit() -> Expr
-      = lhs:primary() _ op:bit_op() _ rhs:primary() {
+        ExprKind::Compare(Box::new(lhs), op_enum, Box::new(rhs)).into()
+      }
+      --
+      lhs:(@) _ "bor" !ident_char() _ rhs:@ {
+        ExprKind::Bit(Box::new(lhs), BitOp::Or, Box::new(rhs)).into()
+      }
+      --
+      lhs:(@) _ "xor" !ident_char() _ rhs:@ {
+        ExprKind::Bit(Box::new(lhs), BitOp::Xor, Box::new(rhs)).into()
+      }
+      --
+      lhs:(@) _ "band" !ident_char() _ rhs:@ {
+        ExprKind::Bit(Box::new(lhs), BitOp::And, Box::new(rhs)).into()
+      }
+      --
+      lhs:(@) _ op:$("shl" / "shr" / "rotl" / "rotr") !ident_char() _ rhs:@ {
         let op_enum = match op {
           "shl" => BitOp::Shl,
           "shr" => BitOp::Shr,
-          "band" => BitOp::And,
-          "bor" => BitOp::Or,
-          "xor" => BitOp::Xor,
           "rotl" => BitOp::Rotl,
           "rotr" => BitOp::Rotr,
           _ => unreachable!(),
         };
 
-        Expr::Bit(Box::new(lhs), op_enum, Box::new(rhs))
+        ExprKind::Bit(Box::new(lhs), op_enum, Box::new(rhs)).into()
+      }
+      --
+      lhs:(@) _ op:$("+" / "-") _ rhs:@ {
+        let op_enum = match op {
+          "+" => BinOp::Add,
+          "-" => BinOp::Sub,
+          _ => unreachable!(),
+        };
+
+        ExprKind::Bin(Box::new(lhs), op_enum, Box::new(rhs)).into()
+      }
+      --
+      lhs:(@) _ op:$("*" / "/") _ rhs:@ {
+        let op_enum = match op {
+          "*" => BinOp::Mul,
+          "/" => BinOp::Div,
+          _ => unreachable!(),
+        };
+
+        ExprKind::Bin(Box::new(lhs), op_enum, Box::new(rhs)).into()
+      }
+      --
+      op:unary_op() _ expr:@ { ExprKind::Unary(Box::new(expr), op).into() }
+      --
+      expr:primary() { expr }
     }
 
+    rule ident_char() = ['a'..='z' | 'A'..='Z' | '_' | '0'..='9']
+
     rule if_expr() -> Expr
-      = "if" _ cond:expr() _ "then" _ then_body:body_expr() _ "end" {
-      Expr::If(Box::new(cond), then_body, None)
+      = spanned(<"if" _ cond:expr() _ "then" _ then_body:body_expr() _ "end" {
+      ExprKind::If(Box::new(cond), then_body, None)
     }
       / "if" _ cond:expr() _ "then" _ then_body:body_expr() _ "else" _ else_body:body_expr() _ "end" {
-      Expr::If(Box::new(cond), then_body, Some(else_body))
-    }
+      ExprKind::If(Box::new(cond), then_body, Some(else_body))
+    }>)
 
     rule loop_expr() -> Expr
-      = "for" _ loop_var:expr() _ "in" _ iter:primary() _ body:body_expr() _ "end" {
-        Expr::Loop(Box::new(loop_var), Box::new(iter), body)
-    }
+      = spanned(<"for" _ loop_var:expr() _ "in" _ iter:primary() _ body:body_expr() _ "end" {
+        ExprKind::Loop(Box::new(loop_var), Box::new(iter), body)
+    }>)
 
     rule fn_expr() -> Expr
-    = "|" _ args:(fn_arg() ** (_ "," _)) _ "|" _ body:body_expr() _ "end" {
-      Expr::Fn(args, body)
-    }
+    = spanned(<"|" _ args:(fn_arg() ** (_ "," _)) _ "|" _ body:body_expr() _ "end" {
+      ExprKind::Fn(args, body)
+    }>)
 
     rule fn_arg() -> (String, Option<Expr>)
     = id:identifier() _ ":" _ ty:typed_expr() {
@@ -332,9 +418,9 @@ parser! {
     }
 
     rule match_expr() -> Expr
-    = "match" _ cond:expr() _ "with" _ cases:match_case() ** _ "end" {
-      Expr::Match(Box::new(cond), cases)
-    }
+    = spanned(<"match" _ cond:expr() _ "with" _ cases:match_case() ** _ "end" {
+      ExprKind::Match(Box::new(cond), cases)
+    }>)
 
     rule match_case() -> (String, Vec<Expr>)
     = "|" _ pat:identifier() _ "->" _ body:body_expr() _ "," _ {
@@ -351,13 +437,6 @@ parser! {
       }
     }
 
-    rule unary() -> Expr
-    = ops:unary_op()** _ expr:primary() {
-      ops.into_iter().rev().fold(expr, |acc, op| {
-        Expr::Unary(Box::new(acc), op)
-      })
-    }
-
     rule body_expr() -> Vec<Expr>
       = expr() ** (_ ";" _)
 
@@ -369,20 +448,245 @@ parser! {
     }
 
     pub rule program() -> Expr
-      = _ exprs:(expr() ** _) _ {
+      = spanned(<_ exprs:(expr() ** _) _ {
         let boxed_exprs = exprs
           .into_iter()
           .map(|e| Box::new(e) as Box<Expr>)
           .collect::<Vec<Box<Expr>>>();
-        Expr::Program(boxed_exprs)
-    }
+        ExprKind::Program(boxed_exprs)
+    }>)
   }
 }
 
+pub type ParseError = peg::error::ParseError<peg::str::LineCol>;
+
+// parses a module and keeps going after syntax errors, a broken top-level
+// item is skipped up to the next line that starts a top-level item (no
+// indentation, not a closing keyword) and parsing resumes from there, so
+// every broken definition is reported in a single run
+pub fn parse_program(source: &str) -> (Expr, Vec<ParseError>) {
+    let sync_points = top_level_lines(source);
+    let mut items = vec![];
+    let mut errors = vec![];
+    let mut start = 0;
+
+    loop {
+        match parse_slice(source, start, source.len()) {
+            Ok(program) => {
+                items.extend(program_items(program));
+                break;
+            }
+
+            Err(err) => {
+                // the furthest failure can lie past the broken item (e.g. on a
+                // missing `end`), the last sync point before it up to which
+                // everything parses is searched for by bisection
+                let candidates = sync_points
+                    .iter()
+                    .copied()
+                    .filter(|&point| point > start && point <= err.location.offset)
+                    .collect::<Vec<_>>();
+                let (mut low, mut high) = (0, candidates.len());
+                let mut prefix = None;
+
+                while low < high {
+                    let mid = (low + high) / 2;
+
+                    match parse_slice(source, start, candidates[mid]) {
+                        Ok(program) => {
+                            prefix = Some((candidates[mid], program));
+                            low = mid + 1;
+                        }
+                        Err(_) => high = mid,
+                    }
+                }
+
+                let mut item_start = start;
+
+                if let Some((point, program)) = prefix {
+                    items.extend(program_items(program));
+                    item_start = point;
+                }
+
+                errors.push(err);
+
+                match sync_points.iter().find(|&&point| point > item_start) {
+                    Some(&next) => start = next,
+                    None => break,
+                }
+            }
+        }
+    }
+
+    (
+        Expr::new(ExprKind::Program(items), Span::new(0, source.len())),
+        errors,
+    )
+}
+
+// parses the items in [start, end) of the source, `start` is the start of
+// a line so only offsets and lines of spans and errors are shifted
+fn parse_slice(source: &str, start: usize, end: usize) -> Result<Expr, ParseError> {
+    esper_parser::program(&source[start..end], start).map_err(|mut err| {
+        err.location.offset += start;
+        err.location.line += source[..start].matches('\n').count();
+        err
+    })
+}
+
+fn program_items(program: Expr) -> std::vec::IntoIter<Box<Expr>> {
+    match program.kind {
+        ExprKind::Program(items) => items.into_iter(),
+        _ => vec![].into_iter(),
+    }
+}
+
+// byte offsets of lines that can start a top-level item
+fn top_level_lines(source: &str) -> Vec<usize> {
+    let mut offset = 0;
+    let mut points = vec![];
+
+    for line in source.split_inclusive('\n') {
+        let word: String = line
+            .chars()
+            .take_while(|c| c.is_ascii_alphabetic() || *c == '_')
+            .collect();
+        let starts_item = line.starts_with('@')
+            || line.starts_with(|c: char| c.is_ascii_digit())
+            || (!word.is_empty()
+                && !matches!(word.as_str(), "end" | "else" | "then" | "with" | "in"));
+
+        if starts_item {
+            points.push(offset);
+        }
+
+        offset += line.len();
+    }
+
+    points
+}
+
+// comments are trivia to the grammar, they are kept aside for tools that
+// print the source back (e.g. the formatter)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub text: String,
+    pub span: Span,
+}
+
+// every `(* ... *)` outside of string and char literals in source order,
+// unterminated comments are left to the parser to report
+pub fn parse_comments(source: &str) -> Vec<Comment> {
+    let mut comments = vec![];
+    let mut offset = 0;
+
+    while let Some(c) = source[offset..].chars().next() {
+        let rest = &source[offset..];
+
+        if let Some(comment) = rest.strip_prefix("(*") {
+            let Some(len) = comment.find("*)").map(|end| end + 4) else {
+                break;
+            };
+
+            comments.push(Comment {
+                text: rest[..len].into(),
+                span: Span::new(offset, offset + len),
+            });
+
+            offset += len;
+        } else if c == '"' {
+            // string literals have no escapes
+            offset += rest[1..].find('"').map_or(rest.len(), |end| end + 2);
+        } else if c == '\'' && rest[1..].chars().nth(1) == Some('\'') {
+            offset += 1 + rest[1..].chars().next().map_or(0, char::len_utf8) + 1;
+        } else {
+            offset += c.len_utf8();
+        }
+    }
+
+    comments
+}
+
+// every node carries the span of the source it was parsed from, nodes
+// synthesized after parsing (e.g. by the emitter) use an empty span
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Expr { kind, span }
+    }
+
+    // whether the expression has a value, the last one of a function body
+    // is returned when it does (from each branch of an `if` or `match`),
+    // definitions, assignments, loops and `pass` are statements
+    pub fn is_value(&self) -> bool {
+        match &self.kind {
+            ExprKind::Int(_)
+            | ExprKind::Float(_)
+            | ExprKind::Bool(_)
+            | ExprKind::Char(_)
+            | ExprKind::String(_)
+            | ExprKind::Var(_)
+            | ExprKind::Bin(_, _, _)
+            | ExprKind::Compare(_, _, _)
+            | ExprKind::Unary(_, _)
+            | ExprKind::Bit(_, _, _)
+            | ExprKind::List(_)
+            | ExprKind::Record(_)
+            | ExprKind::Construct(_, _)
+            | ExprKind::Range(_, _)
+            | ExprKind::Member(_)
+            | ExprKind::Call(_, _)
+            | ExprKind::TypedCall(_, _, _)
+            | ExprKind::Fn(_, _)
+            | ExprKind::If(_, _, _)
+            | ExprKind::Match(_, _) => true,
+
+            ExprKind::Directive(_, inner) => inner.is_value(),
+            _ => false,
+        }
+    }
+}
+
+// a parameter of a function with its type, if declared
+pub type Param = (String, Option<Expr>);
+
+// a method of a struct entry, its parameters (without a `self` receiver),
+// body and declared return type
+pub fn method(entry: &Expr) -> Option<(&[Param], &[Expr], Option<&Expr>)> {
+    let (function, return_type) = match &entry.kind {
+        ExprKind::TypedLet(_, _, ty, function) => (function.as_ref(), Some(ty.as_ref())),
+        _ => (entry, None),
+    };
+
+    match &function.kind {
+        ExprKind::Fn(params, body) => Some((receiver(params).1, body, return_type)),
+        _ => None,
+    }
+}
+
+// a leading untyped `self` parameter is the receiver of a method
+pub fn receiver(params: &[Param]) -> (bool, &[Param]) {
+    match params.split_first() {
+        Some(((name, None), rest)) if name == "self" => (true, rest),
+        _ => (false, params),
+    }
+}
+
+impl From<ExprKind> for Expr {
+    fn from(kind: ExprKind) -> Self {
+        Expr::new(kind, Span::default())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
-pub enum Expr {
+pub enum ExprKind {
     Program(Vec<Box<Expr>>),
-    Let(String, Box<Expr>),
+    Let(String, Vec<Expr>, Box<Expr>),
     Assign(Box<Expr>, Box<Expr>),
     Var(String),
     Int(i64),
@@ -405,7 +709,8 @@ pub enum Expr {
     Fn(Vec<(String, Option<Expr>)>, Vec<Expr>),
     Member(Vec<Expr>),
     Call(Box<Expr>, Vec<Expr>),
-    Struct(String, Vec<(String, Expr)>),
+    Struct(String, Vec<Expr>, Vec<(String, Expr)>),
+    Construct(String, Box<Expr>),
     TypedSymbol(String),
     TypedFn(Box<Expr>),
     TypedUnary(Box<Expr>),
@@ -415,7 +720,7 @@ pub enum Expr {
     TypedOptional(Box<Expr>),
     TypedSymbolGeneric(String, Vec<Expr>),
     TypedVariant(Box<Expr>, Box<Expr>),
-    TypedLet(String, Box<Expr>, Box<Expr>),
+    TypedLet(String, Vec<Expr>, Box<Expr>, Box<Expr>),
     TypeAlias(String, Vec<Expr>, Box<Expr>),
     TypedCall(Box<Expr>, Vec<Expr>, Vec<Expr>),
 }
@@ -0,0 +1,432 @@
+use crate::diagnostics::render_message;
+use crate::error::CompileError;
+use crate::parse;
+use crate::parser::{Expr, ExprKind};
+use crate::session::{Segment, Session};
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::process::{self, Command};
+
+const PROMPT: &str = "esper> ";
+const CONTINUATION_PROMPT: &str = "  ...> ";
+
+// path the inputs are reported against
+const REPL_PATH: &str = "<repl>";
+
+// definitions entered so far, each input is wrapped into a synthetic
+// module along with them and built and run from scratch, so only
+// definitions outlive the input they were entered in
+struct Repl {
+    session: Session,
+    definitions: Vec<Definition>,
+    build_dir: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+struct Definition {
+    name: String,
+    source: String,
+    // offset into the input being evaluated, none for earlier inputs
+    origin: Option<usize>,
+}
+
+// an input that is not a definition, run in the generated main and printed
+// when it is a value
+#[derive(Debug, Clone)]
+struct Statement {
+    source: String,
+    origin: usize,
+    prints: bool,
+}
+
+// reads inputs until the end of input or `:quit`, an input spans as many
+// lines as it takes for every block to be closed with `end` (an empty
+// line submits it regardless)
+pub fn run(input: impl BufRead, mut output: impl Write, session: Session) -> io::Result<()> {
+    let build_dir = env::temp_dir().join(format!("esper-{}-repl", process::id()));
+    fs::create_dir_all(&build_dir)?;
+
+    let mut repl = Repl {
+        session,
+        definitions: vec![],
+        build_dir,
+    };
+
+    let mut lines = input.lines();
+    let mut buffer = String::new();
+
+    let result = loop {
+        let prompt = if buffer.is_empty() {
+            PROMPT
+        } else {
+            CONTINUATION_PROMPT
+        };
+
+        write!(output, "{}", prompt)?;
+        output.flush()?;
+
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => {
+                writeln!(output)?;
+                break Ok(());
+            }
+        };
+
+        if buffer.is_empty() {
+            match line.trim() {
+                "" => continue,
+                ":quit" | ":q" => break Ok(()),
+
+                ":reset" => {
+                    repl.definitions.clear();
+                    continue;
+                }
+
+                ":defs" => {
+                    for definition in &repl.definitions {
+                        writeln!(output, "{}", definition.source)?;
+                    }
+
+                    continue;
+                }
+
+                _ => {}
+            }
+        }
+
+        let submit = line.trim().is_empty();
+        buffer.push_str(&line);
+        buffer.push('\n');
+
+        if submit || open_blocks(&buffer) <= 0 {
+            let source = std::mem::take(&mut buffer);
+
+            if let Err(err) = repl.eval(&source, &mut output) {
+                break Err(err);
+            }
+        }
+    };
+
+    let _ = fs::remove_dir_all(&repl.build_dir);
+    result
+}
+
+impl Repl {
+    fn eval(&mut self, source: &str, output: &mut impl Write) -> io::Result<()> {
+        let mut session = self.session.clone();
+        let id = session.add_source(REPL_PATH, source);
+
+        // names are resolved against the synthetic module, an input on
+        // its own refers to definitions of earlier inputs
+        let program = match parse(source) {
+            Ok(program) => program,
+
+            Err(diagnostics) => {
+                for diagnostic in diagnostics {
+                    session.report(id, &diagnostic);
+                }
+
+                let summary = session.summary();
+                return self.report(&mut session, CompileError::Parse(summary), output);
+            }
+        };
+
+        let ExprKind::Program(items) = &program.root.kind else {
+            return Ok(());
+        };
+
+        let mut definitions = vec![];
+        let mut statements = vec![];
+
+        for item in items {
+            let item_source = source[item.span.start..item.span.end].to_string();
+
+            match definition_name(item) {
+                Some(name) if name == "main" => {
+                    let message = "`main` is generated by the repl and cannot be redefined";
+                    let rendered =
+                        render_message(session.message_format, "error", Some(REPL_PATH), message);
+
+                    return writeln!(output, "{}", rendered);
+                }
+
+                Some(name) => definitions.push(Definition {
+                    name,
+                    source: item_source,
+                    origin: Some(item.span.start),
+                }),
+
+                None => statements.push(Statement {
+                    source: item_source,
+                    origin: item.span.start,
+                    prints: prints_value(item),
+                }),
+            }
+        }
+
+        // later definitions shadow earlier ones of the same name, these
+        // would otherwise be redefinitions in C++
+        let mut session_definitions = self.definitions.clone();
+        session_definitions.retain(|old| definitions.iter().all(|new| new.name != old.name));
+        session_definitions.extend(definitions);
+
+        // a call can return void, in which case it is run without printing
+        let printed = self.module(&session_definitions, &statements, true);
+        let mut result = self.build_and_run(source, printed, output)?;
+
+        if result.is_err() && statements.iter().any(|statement| statement.prints) {
+            let unprinted = self.module(&session_definitions, &statements, false);
+            result = self.build_and_run(source, unprinted, output)?;
+        }
+
+        match result {
+            Ok(()) => {
+                self.definitions = session_definitions
+                    .into_iter()
+                    .map(|definition| Definition {
+                        origin: None,
+                        ..definition
+                    })
+                    .collect();
+                Ok(())
+            }
+
+            Err((mut session, err)) => self.report(&mut session, err, output),
+        }
+    }
+
+    // the synthetic module holding every definition and a main function
+    // running the statements of the current input, along with the ranges
+    // of it copied from the input
+    fn module(
+        &self,
+        definitions: &[Definition],
+        statements: &[Statement],
+        print_values: bool,
+    ) -> (String, Vec<Segment>) {
+        let mut module = String::new();
+        let mut segments = vec![];
+
+        let mut copy = |module: &mut String, source: &str, origin: Option<usize>| {
+            if let Some(origin_start) = origin {
+                segments.push(Segment {
+                    start: module.len(),
+                    origin_start,
+                    len: source.len(),
+                });
+            }
+
+            module.push_str(source);
+        };
+
+        for definition in definitions {
+            copy(&mut module, &definition.source, definition.origin);
+            module.push('\n');
+        }
+
+        module.push_str("let main: int = |argc: int, argv: vector<string>|\n");
+
+        for statement in statements {
+            if print_values && statement.prints {
+                module.push_str("  esper.print(");
+                copy(&mut module, &statement.source, Some(statement.origin));
+                module.push_str(");\n  esper.print(\"\\n\");\n");
+            } else {
+                module.push_str("  ");
+                copy(&mut module, &statement.source, Some(statement.origin));
+                module.push_str(";\n");
+            }
+        }
+
+        module.push_str("  0\nend\n");
+        (module, segments)
+    }
+
+    // the failed session holds the diagnostics rendered against the input
+    // (or the synthetic module outside of it), the program's output is
+    // written as is
+    fn build_and_run(
+        &self,
+        input: &str,
+        (module, segments): (String, Vec<Segment>),
+        output: &mut impl Write,
+    ) -> io::Result<Result<(), (Session, CompileError)>> {
+        let mut session = self.session.clone();
+        session.use_prelude = true;
+
+        let input_id = session.add_source(REPL_PATH, input);
+        let id = session.add_generated_source(REPL_PATH, module, input_id, segments);
+        let exe_path = self.build_dir.join("repl");
+
+        if let Err(err) = session.build(id, &exe_path) {
+            return Ok(Err((session, err)));
+        }
+
+        let run = Command::new(&exe_path).output()?;
+        output.write_all(&run.stdout)?;
+        output.write_all(&run.stderr)?;
+
+        if !run.status.success() {
+            let message = match run.status.code() {
+                Some(code) => format!("program exited with status {}", code),
+                None => "program was terminated by a signal".into(),
+            };
+
+            let rendered = render_message(session.message_format, "error", None, &message);
+            writeln!(output, "{}", rendered)?;
+        }
+
+        Ok(Ok(()))
+    }
+
+    fn report(
+        &self,
+        session: &mut Session,
+        err: CompileError,
+        output: &mut impl Write,
+    ) -> io::Result<()> {
+        for message in session.take_messages() {
+            writeln!(output, "{}", message)?;
+        }
+
+        let format = session.message_format;
+
+        match err {
+            CompileError::Parse(summary) | CompileError::Semantic(summary) => {
+                writeln!(output, "{}", summary)
+            }
+
+            CompileError::Backend(msg) => {
+                writeln!(output, "{}", render_message(format, "error", None, &msg))
+            }
+
+            err => writeln!(output, "{}", err),
+        }
+    }
+}
+
+// name of the top-level item when it is kept as a definition
+fn definition_name(expr: &Expr) -> Option<String> {
+    match &expr.kind {
+        ExprKind::Let(name, _, _)
+        | ExprKind::TypedLet(name, _, _, _)
+        | ExprKind::TypeAlias(name, _, _)
+        | ExprKind::Struct(name, _, _) => Some(name.clone()),
+
+        ExprKind::Directive(_, inner) => definition_name(inner),
+        _ => None,
+    }
+}
+
+// expressions whose value is printed, lists, ranges and records have no
+// stream operator and statements have no value
+fn prints_value(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Member(members) => !matches!(
+            &members[..],
+            [object, method] if matches!(&object.kind, ExprKind::Var(name) if name == "esper")
+                && matches!(&method.kind, ExprKind::Call(callee, _)
+                    if matches!(&callee.kind, ExprKind::Var(name) if name == "print"))
+        ),
+
+        ExprKind::Int(_)
+        | ExprKind::Float(_)
+        | ExprKind::Bool(_)
+        | ExprKind::Char(_)
+        | ExprKind::String(_)
+        | ExprKind::Var(_)
+        | ExprKind::Bin(_, _, _)
+        | ExprKind::Compare(_, _, _)
+        | ExprKind::Bit(_, _, _)
+        | ExprKind::Unary(_, _)
+        | ExprKind::Call(_, _)
+        | ExprKind::TypedCall(_, _, _) => true,
+
+        _ => false,
+    }
+}
+
+// number of blocks left open by `if`, `for`, `match`, `struct`, `type` and
+// function literals (`|a, b| ... end`), comments and literals are skipped
+pub fn open_blocks(source: &str) -> isize {
+    let tokens = tokens(source);
+    let mut blocks: Vec<&str> = vec![];
+    let mut closed = 0;
+    let mut i = 0;
+
+    while i < tokens.len() {
+        match tokens[i] {
+            "if" | "for" | "match" | "struct" | "type" => blocks.push(tokens[i]),
+
+            // an `end` without an open block closes an outer one
+            "end" => closed += isize::from(blocks.pop().is_none()),
+
+            "|" => {
+                let next_pipe = tokens[i + 1..].iter().position(|&token| token == "|");
+                let params = &tokens[i + 1..next_pipe.map_or(tokens.len(), |pipe| i + 1 + pipe)];
+
+                // `| pat -> body,` is a match case, inside of types only
+                // pipes around typed parameters open a function type
+                // (`| a | b` is a variant)
+                let is_fn = if blocks.last() == Some(&"type") {
+                    params.is_empty() || params.contains(&":")
+                } else {
+                    !params.contains(&"->")
+                };
+
+                if is_fn {
+                    blocks.push("|");
+                    i += params.len() + 1;
+                }
+            }
+
+            _ => {}
+        }
+
+        i += 1;
+    }
+
+    blocks.len() as isize - closed
+}
+
+// words and punctuation, whitespace, comments and literals are dropped
+fn tokens(source: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    let mut offset = 0;
+
+    while let Some(c) = source[offset..].chars().next() {
+        let rest = &source[offset..];
+
+        let len = if let Some(comment) = rest.strip_prefix("(*") {
+            comment.find("*)").map_or(rest.len(), |end| end + 4)
+        } else if rest.starts_with("->") {
+            tokens.push("->");
+            2
+        } else if c == '"' {
+            rest[1..].find('"').map_or(rest.len(), |end| end + 2)
+        } else if c == '\'' && rest[1..].chars().nth(1) == Some('\'') {
+            1 + rest[1..].chars().next().map_or(0, char::len_utf8) + 1
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+
+            tokens.push(&rest[..len]);
+            len
+        } else {
+            if !c.is_whitespace() {
+                tokens.push(&rest[..c.len_utf8()]);
+            }
+
+            c.len_utf8()
+        };
+
+        offset += len;
+    }
+
+    tokens
+}
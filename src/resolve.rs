@@ -0,0 +1,505 @@
+use crate::check::Pass;
+use crate::diagnostics::{Diagnostic, DUPLICATE_DEFINITION, UNDEFINED_NAME};
+use crate::parser::{Expr, ExprKind};
+use crate::span::{name_span, LineIndex, Span};
+use crate::visit::{EsperContext, Visitor};
+
+use std::collections::HashSet;
+
+// names the emitted C++ provides beyond the module, the prelude's `esper`
+// object and those of the std and C library headers it includes (the C++
+// output is `using namespace std`), anything else must be defined by the
+// module unless undefined names are allowed
+const KNOWN_NAMES: &[&str] = &[
+    "esper",
+    "std",
+    // <iostream>, <cstdio>
+    "cout",
+    "cin",
+    "cerr",
+    "clog",
+    "endl",
+    "flush",
+    "getline",
+    "printf",
+    "scanf",
+    "puts",
+    "putchar",
+    "getchar",
+    "fprintf",
+    "sprintf",
+    "snprintf",
+    "stdout",
+    "stderr",
+    "stdin",
+    // <string>
+    "to_string",
+    "stoi",
+    "stol",
+    "stoll",
+    "stoul",
+    "stoull",
+    "stof",
+    "stod",
+    "stold",
+    // <cstdlib>
+    "atoi",
+    "atol",
+    "atof",
+    "strtol",
+    "strtod",
+    "rand",
+    "srand",
+    "exit",
+    "abort",
+    "getenv",
+    "system",
+    "labs",
+    "div",
+    // <cstring>, <cctype>
+    "strlen",
+    "strcmp",
+    "strcpy",
+    "strcat",
+    "memcpy",
+    "memset",
+    "isalpha",
+    "isdigit",
+    "isalnum",
+    "isspace",
+    "isupper",
+    "islower",
+    "toupper",
+    "tolower",
+    // <cmath>
+    "abs",
+    "fabs",
+    "sqrt",
+    "cbrt",
+    "pow",
+    "exp",
+    "exp2",
+    "log",
+    "log2",
+    "log10",
+    "sin",
+    "cos",
+    "tan",
+    "asin",
+    "acos",
+    "atan",
+    "atan2",
+    "sinh",
+    "cosh",
+    "tanh",
+    "hypot",
+    "floor",
+    "ceil",
+    "round",
+    "trunc",
+    "fmod",
+    "fmin",
+    "fmax",
+    "isnan",
+    "isinf",
+    // <algorithm>, <numeric>, <utility>
+    "min",
+    "max",
+    "clamp",
+    "swap",
+    "move",
+    "forward",
+    "sort",
+    "stable_sort",
+    "reverse",
+    "find",
+    "find_if",
+    "count",
+    "count_if",
+    "any_of",
+    "all_of",
+    "none_of",
+    "for_each",
+    "transform",
+    "copy",
+    "fill",
+    "unique",
+    "remove",
+    "remove_if",
+    "min_element",
+    "max_element",
+    "accumulate",
+    "iota",
+    "reduce",
+    "gcd",
+    "lcm",
+    "make_pair",
+    "make_tuple",
+    "get",
+    "begin",
+    "end",
+    "size",
+    "views",
+    "ranges",
+    // <variant>, <optional>, <memory>
+    "visit",
+    "holds_alternative",
+    "get_if",
+    "nullopt",
+    "make_optional",
+    "make_shared",
+    "make_unique",
+    // types called as constructors
+    "string",
+    "vector",
+    "array",
+    "deque",
+    "list",
+    "map",
+    "set",
+    "unordered_map",
+    "unordered_set",
+    "pair",
+    "tuple",
+    "optional",
+    "variant",
+];
+
+// checks that every variable refers to a definition in scope and that no
+// scope defines a name twice, scopes are those of the C++ output: the
+// module, functions (with their parameters), struct bodies (fields are
+// visible to methods), loops (with their bindings), branches of `if` and
+// arms of `match` (with the matched value as `_`)
+pub struct Resolver {
+    // names the module does not define are warnings rather than errors,
+    // left to the C++ compiler
+    pub allow_undefined: bool,
+}
+
+struct ResolveContext;
+
+impl EsperContext for ResolveContext {
+    fn new() -> Self {
+        ResolveContext
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Scope {
+    span: Span,
+    // the node introducing the scope, a struct is not in its own scope
+    owner: Span,
+}
+
+#[derive(Debug, Clone)]
+struct Definition {
+    name: String,
+    name_span: Span,
+    span: Span,
+    // the innermost scope around the definition when not set
+    scope: Option<Span>,
+    // variables are visible after their definition, functions (for
+    // recursion), parameters and fields from the start of their scope
+    visible_from: usize,
+}
+
+impl Pass for Resolver {
+    fn run(&self, program: &Expr, source: &str) -> Vec<Diagnostic> {
+        let (scopes, mut definitions, references) = collect(program, source);
+        let lines = LineIndex::new(source);
+        let mut diagnostics = vec![];
+
+        for def in definitions.iter_mut() {
+            if def.scope.is_none() {
+                def.scope = scopes
+                    .iter()
+                    .filter(|scope| scope.owner != def.span && contains(scope.span, def.span))
+                    .map(|scope| scope.span)
+                    .min_by_key(|span| span.len());
+            }
+        }
+
+        let mut seen: Vec<&Definition> = vec![];
+
+        for def in &definitions {
+            let previous = seen
+                .iter()
+                .find(|prev| prev.name == def.name && prev.scope == def.scope);
+
+            match previous {
+                Some(prev) => diagnostics.push(
+                    Diagnostic::error(
+                        format!("the name `{}` is defined multiple times", def.name),
+                        def.name_span,
+                    )
+                    .with_code(DUPLICATE_DEFINITION)
+                    .with_label("redefined here")
+                    .with_note(format!(
+                        "`{}` was previously defined on line {}",
+                        def.name,
+                        lines.line(prev.name_span.start)
+                    )),
+                ),
+
+                None => seen.push(def),
+            }
+        }
+
+        for (name, span) in references {
+            let mut in_scope = definitions.iter().filter(|def| {
+                def.name == name && def.scope.is_some_and(|scope| contains(scope, span))
+            });
+
+            if in_scope.clone().any(|def| def.visible_from <= span.start)
+                || KNOWN_NAMES.contains(&name.as_str())
+            {
+                continue;
+            }
+
+            let diagnostic = match in_scope.next() {
+                Some(def) => {
+                    Diagnostic::error(format!("cannot find value `{}` in this scope", name), span)
+                        .with_label("not found in this scope")
+                        .with_note(format!(
+                            "`{}` is defined on line {}, it is only visible after its definition",
+                            name,
+                            lines.line(def.name_span.start)
+                        ))
+                }
+
+                None if self.allow_undefined => Diagnostic::warning(
+                    format!("cannot find value `{}` in this module", name),
+                    span,
+                )
+                .with_label("not defined in this module")
+                .with_note("it is left to the C++ compiler with `--allow-undefined`"),
+
+                None => {
+                    Diagnostic::error(format!("cannot find value `{}` in this scope", name), span)
+                        .with_label("not found in this scope")
+                        .with_note(
+                            "names from C++ libraries that are not known can be allowed with `--allow-undefined`",
+                        )
+                }
+            };
+
+            diagnostics.push(diagnostic.with_code(UNDEFINED_NAME));
+        }
+
+        diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+        diagnostics
+    }
+}
+
+fn collect(program: &Expr, source: &str) -> (Vec<Scope>, Vec<Definition>, Vec<(String, Span)>) {
+    let mut scopes = vec![Scope {
+        span: program.span,
+        owner: program.span,
+    }];
+
+    let mut definitions = vec![];
+    let mut references = vec![];
+
+    // variables naming members, record keys and loop bindings are not
+    // references, neither is anything within types and directives
+    let mut ignored: HashSet<Span> = HashSet::new();
+    let mut ignored_ranges: Vec<Span> = vec![];
+
+    // typed methods are typed definitions, defined as entries of the struct
+    let mut methods: HashSet<Span> = HashSet::new();
+
+    program.visit(&mut ResolveContext, &mut |_, expr| {
+        if ignored_ranges
+            .iter()
+            .any(|range| contains(*range, expr.span))
+        {
+            return;
+        }
+
+        let mut define = |name: &str, name_span: Span, scope: Option<Span>, visible_from| {
+            definitions.push(Definition {
+                name: name.into(),
+                name_span,
+                span: expr.span,
+                scope,
+                visible_from,
+            })
+        };
+
+        match &expr.kind {
+            ExprKind::Let(name, _, value) | ExprKind::TypedLet(name, _, _, value)
+                if !methods.contains(&expr.span) =>
+            {
+                let visible_from = match value.kind {
+                    ExprKind::Fn(_, _) => expr.span.start,
+                    _ => expr.span.end,
+                };
+
+                define(name, name_span(source, expr.span, name), None, visible_from);
+            }
+
+            ExprKind::Struct(name, _, entries) => {
+                define(
+                    name,
+                    name_span(source, expr.span, name),
+                    None,
+                    expr.span.start,
+                );
+
+                for (entry, value) in entries {
+                    if let ExprKind::TypedLet(_, _, _, _) = value.kind {
+                        methods.insert(value.span);
+                    }
+
+                    define(
+                        entry,
+                        name_span(source, expr.span, entry),
+                        Some(expr.span),
+                        expr.span.start,
+                    );
+                }
+
+                scopes.push(Scope {
+                    span: expr.span,
+                    owner: expr.span,
+                });
+            }
+
+            ExprKind::TypeAlias(name, _, _) => define(
+                name,
+                name_span(source, expr.span, name),
+                None,
+                expr.span.start,
+            ),
+
+            ExprKind::Fn(params, _) => {
+                for (param, _) in params {
+                    define(
+                        param,
+                        name_span(source, expr.span, param),
+                        Some(expr.span),
+                        expr.span.start,
+                    );
+                }
+
+                scopes.push(Scope {
+                    span: expr.span,
+                    owner: expr.span,
+                });
+            }
+
+            // bindings are not visible to the iterated expression
+            ExprKind::Loop(pattern, iter, _) => {
+                let bindings = match &pattern.kind {
+                    ExprKind::List(items) => items.iter().collect(),
+                    _ => vec![pattern.as_ref()],
+                };
+
+                for binding in bindings {
+                    if let ExprKind::Var(name) = &binding.kind {
+                        define(name, binding.span, Some(expr.span), iter.span.end);
+                        ignored.insert(binding.span);
+                    }
+                }
+
+                scopes.push(Scope {
+                    span: expr.span,
+                    owner: expr.span,
+                });
+            }
+
+            ExprKind::If(_, then_body, else_body) => {
+                for body in std::iter::once(then_body).chain(else_body) {
+                    if let Some(span) = block_span(body) {
+                        scopes.push(Scope {
+                            span,
+                            owner: expr.span,
+                        });
+                    }
+                }
+            }
+
+            // every arm is a branch of the generated visitor taking `_`
+            ExprKind::Match(_, cases) => {
+                for (_, body) in cases {
+                    if let Some(span) = block_span(body) {
+                        define("_", span, Some(span), span.start);
+
+                        scopes.push(Scope {
+                            span,
+                            owner: expr.span,
+                        });
+                    }
+                }
+            }
+
+            ExprKind::Member(members) => {
+                for member in members.iter().skip(1) {
+                    member_names(member, &mut ignored);
+                }
+            }
+
+            ExprKind::Record(entries) => {
+                for entry in entries {
+                    if let Some(key) = entry.first() {
+                        ignored.insert(key.span);
+                    }
+                }
+            }
+
+            ExprKind::Directive(directive, _) => ignored_ranges.push(directive.span),
+
+            ExprKind::TypedSymbol(_)
+            | ExprKind::TypedFn(_)
+            | ExprKind::TypedUnary(_)
+            | ExprKind::TypedLiteral(_)
+            | ExprKind::TypedMember(_)
+            | ExprKind::TypedRecord(_)
+            | ExprKind::TypedOptional(_)
+            | ExprKind::TypedSymbolGeneric(_, _)
+            | ExprKind::TypedVariant(_, _) => ignored_ranges.push(expr.span),
+
+            ExprKind::Construct(name, _) => references.push((
+                name.clone(),
+                Span::new(expr.span.start, expr.span.start + name.len()),
+            )),
+
+            ExprKind::Var(name) if !ignored.contains(&expr.span) => {
+                references.push((name.clone(), expr.span))
+            }
+
+            _ => {}
+        }
+    });
+
+    (scopes, definitions, references)
+}
+
+// `a.b.f(x)` refers to `a` only, `b` and `f` are members
+fn member_names(member: &Expr, ignored: &mut HashSet<Span>) {
+    match &member.kind {
+        ExprKind::Var(_) => {
+            ignored.insert(member.span);
+        }
+
+        ExprKind::Call(callee, _) | ExprKind::TypedCall(callee, _, _) => {
+            ignored.insert(callee.span);
+        }
+
+        ExprKind::Member(members) => {
+            for member in members {
+                member_names(member, ignored);
+            }
+        }
+
+        _ => {}
+    }
+}
+
+fn block_span(body: &[Expr]) -> Option<Span> {
+    Some(body.first()?.span.to(body.last()?.span))
+}
+
+fn contains(outer: Span, inner: Span) -> bool {
+    outer.start <= inner.start && inner.end <= outer.end
+}
@@ -0,0 +1,406 @@
+use crate::cc::{ClangCXX, CxxCompiler, SourceFile};
+use crate::diagnostics::{render_message, Diagnostic, MessageFormat};
+use crate::error::CompileError;
+use crate::interp;
+use crate::span::{LineIndex, Span};
+use crate::{emit_cpp, parse, Capture, Options, Program};
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+// names a module namespace cannot take, `main` is the entry point of the
+// emitted C++ and `std` is where the standard library lives
+const RESERVED_MODULE_IDS: &[&str] = &[
+    "main",
+    "std",
+    "alignas",
+    "alignof",
+    "and",
+    "and_eq",
+    "asm",
+    "auto",
+    "bitand",
+    "bitor",
+    "bool",
+    "break",
+    "case",
+    "catch",
+    "char",
+    "char8_t",
+    "char16_t",
+    "char32_t",
+    "class",
+    "compl",
+    "concept",
+    "const",
+    "consteval",
+    "constexpr",
+    "constinit",
+    "const_cast",
+    "continue",
+    "co_await",
+    "co_return",
+    "co_yield",
+    "decltype",
+    "default",
+    "delete",
+    "do",
+    "double",
+    "dynamic_cast",
+    "else",
+    "enum",
+    "explicit",
+    "export",
+    "extern",
+    "false",
+    "float",
+    "for",
+    "friend",
+    "goto",
+    "if",
+    "inline",
+    "int",
+    "long",
+    "mutable",
+    "namespace",
+    "new",
+    "noexcept",
+    "not",
+    "not_eq",
+    "nullptr",
+    "operator",
+    "or",
+    "or_eq",
+    "private",
+    "protected",
+    "public",
+    "register",
+    "reinterpret_cast",
+    "requires",
+    "return",
+    "short",
+    "signed",
+    "sizeof",
+    "static",
+    "static_assert",
+    "static_cast",
+    "struct",
+    "switch",
+    "template",
+    "this",
+    "thread_local",
+    "throw",
+    "true",
+    "try",
+    "typedef",
+    "typeid",
+    "typename",
+    "union",
+    "unsigned",
+    "using",
+    "virtual",
+    "void",
+    "volatile",
+    "wchar_t",
+    "while",
+    "xor",
+    "xor_eq",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileId(usize);
+
+#[derive(Debug, Clone)]
+struct SessionFile {
+    path: String,
+    source: String,
+    // set for modules generated from another file, e.g. the repl wraps its
+    // inputs into a module of their own
+    origin: Option<(FileId, Vec<Segment>)>,
+}
+
+// a range of a generated module copied from the file it was generated from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    pub start: usize,
+    pub origin_start: usize,
+    pub len: usize,
+}
+
+impl Segment {
+    // the offset in the origin, for offsets within the segment
+    fn origin_offset(&self, offset: usize) -> Option<usize> {
+        (self.start..=self.start + self.len)
+            .contains(&offset)
+            .then(|| self.origin_start + offset - self.start)
+    }
+}
+
+// builds any number of modules with the same C++ compiler and flags, every
+// diagnostic (including the remapped ones from the C++ compiler) is kept as
+// a rendered message until the caller takes it rather than being printed
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub cxx: CxxCompiler,
+    pub cxx_flags: Vec<String>,
+    pub use_prelude: bool,
+    pub exhaustive_match: bool,
+    // parses without resolving names or checking types, e.g. for emitting
+    // modules that are only meant to show the generated C++
+    pub skip_checks: bool,
+    // names the module does not define are left to the C++ compiler
+    pub allow_undefined: bool,
+    pub capture: Capture,
+    pub message_format: MessageFormat,
+    files: Vec<SessionFile>,
+    messages: Vec<String>,
+    error_count: usize,
+}
+
+impl Session {
+    pub fn new(cxx: CxxCompiler) -> Self {
+        Session {
+            cxx,
+            cxx_flags: vec![],
+            use_prelude: false,
+            exhaustive_match: false,
+            skip_checks: false,
+            allow_undefined: false,
+            capture: Capture::Reference,
+            message_format: MessageFormat::Human,
+            files: vec![],
+            messages: vec![],
+            error_count: 0,
+        }
+    }
+
+    pub fn add_file(&mut self, path: &Path) -> Result<FileId, CompileError> {
+        let source =
+            fs::read_to_string(path).map_err(|err| CompileError::Io(path.to_path_buf(), err))?;
+
+        Ok(self.add_source(path.to_string_lossy(), source))
+    }
+
+    // the path is only used in diagnostics and to name the module
+    pub fn add_source(&mut self, path: impl Into<String>, source: impl Into<String>) -> FileId {
+        self.files.push(SessionFile {
+            path: path.into(),
+            source: source.into(),
+            origin: None,
+        });
+
+        FileId(self.files.len() - 1)
+    }
+
+    // diagnostics within the segments are reported against the origin, the
+    // others against the generated module
+    pub fn add_generated_source(
+        &mut self,
+        path: impl Into<String>,
+        source: impl Into<String>,
+        origin: FileId,
+        segments: Vec<Segment>,
+    ) -> FileId {
+        let id = self.add_source(path, source);
+        self.files[id.0].origin = Some((origin, segments));
+        id
+    }
+
+    // the file and span a diagnostic in a generated module points into
+    fn origin(&self, id: FileId, span: Span) -> (FileId, Span) {
+        let Some((origin, segments)) = &self.files[id.0].origin else {
+            return (id, span);
+        };
+
+        segments
+            .iter()
+            .find_map(|segment| {
+                let start = segment.origin_offset(span.start)?;
+                let end = segment.origin_offset(span.end)?;
+                Some((*origin, Span::new(start, end)))
+            })
+            .unwrap_or((id, span))
+    }
+
+    // lines of a generated module mapped to the lines of its origin they
+    // were copied from, none for the lines generated around them
+    fn origin_lines(&self, id: FileId) -> Option<(FileId, Vec<Option<usize>>)> {
+        let (origin, segments) = self.files[id.0].origin.as_ref()?;
+        let origin_lines = LineIndex::new(self.source(*origin));
+        let mut line_start = 0;
+
+        let lines = self
+            .source(id)
+            .split('\n')
+            .map(|line| {
+                let line_end = line_start + line.len();
+                let copied = segments.iter().find_map(|segment| {
+                    let start = line_start.max(segment.start);
+                    (start < line_end.min(segment.start + segment.len))
+                        .then(|| origin_lines.line(segment.origin_start + start - segment.start))
+                });
+
+                line_start = line_end + 1;
+                copied
+            })
+            .collect();
+
+        Some((*origin, lines))
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = FileId> {
+        (0..self.files.len()).map(FileId)
+    }
+
+    pub fn path(&self, id: FileId) -> &str {
+        &self.files[id.0].path
+    }
+
+    pub fn source(&self, id: FileId) -> &str {
+        &self.files[id.0].source
+    }
+
+    // modules are emitted into a namespace named after the file, paths
+    // that are not identifiers (e.g. `<repl>`) or reserved in C++ fall back
+    // to module
+    pub fn module_id(&self, id: FileId) -> String {
+        Path::new(self.path(id))
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .filter(|stem| {
+                !stem.starts_with(|c: char| c.is_ascii_digit())
+                    && !stem.is_empty()
+                    && stem.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                    && !RESERVED_MODULE_IDS.contains(stem)
+            })
+            .unwrap_or("module")
+            .into()
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.error_count
+    }
+
+    pub fn take_messages(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.messages)
+    }
+
+    // rendered in the message format, e.g. to close a failed build
+    pub fn summary(&self) -> String {
+        let message = format!(
+            "aborting due to {} previous error{}",
+            self.error_count,
+            if self.error_count == 1 { "" } else { "s" }
+        );
+
+        render_message(self.message_format, "error", None, &message)
+    }
+
+    // parses and runs the semantic passes, errors carry the summary after
+    // every diagnostic of the module was reported
+    pub fn check(&mut self, id: FileId) -> Result<Program, CompileError> {
+        match parse(self.source(id)) {
+            Ok(program) if self.skip_checks => Ok(program),
+
+            Ok(program) => {
+                let diagnostics = program.check(self.allow_undefined);
+                let has_errors = diagnostics.has_errors();
+
+                for diagnostic in diagnostics {
+                    self.report(id, &diagnostic);
+                }
+
+                if has_errors {
+                    Err(CompileError::Semantic(self.summary()))
+                } else {
+                    Ok(program)
+                }
+            }
+
+            Err(diagnostics) => {
+                for diagnostic in diagnostics {
+                    self.report(id, &diagnostic);
+                }
+
+                Err(CompileError::Parse(self.summary()))
+            }
+        }
+    }
+
+    pub fn report(&mut self, id: FileId, diagnostic: &Diagnostic) {
+        let (id, span) = self.origin(id, diagnostic.span);
+        let diagnostic = Diagnostic {
+            span,
+            ..diagnostic.clone()
+        };
+        let file = &self.files[id.0];
+        let rendered = diagnostic.emit(self.message_format, &file.path, &file.source);
+
+        if diagnostic.is_error() {
+            self.error_count += 1;
+        }
+
+        self.messages.push(rendered);
+    }
+
+    // emitted files stay readable, #line directives are only needed when
+    // the C++ compiler reports against the esper source
+    pub fn emit_cpp(&mut self, id: FileId, line_directives: bool) -> Result<String, CompileError> {
+        let program = self.check(id)?;
+        let options = Options {
+            module_id: self.module_id(id),
+            use_prelude: self.use_prelude,
+            cxx_flavor: self.cxx.flavor,
+            exhaustive_match: self.exhaustive_match,
+            capture: self.capture,
+            line_directives: line_directives.then(|| self.path(id).to_string()),
+        };
+
+        Ok(emit_cpp(&program, options))
+    }
+
+    pub fn build(&mut self, id: FileId, output_path: &Path) -> Result<(), CompileError> {
+        let cxx_source = self.emit_cpp(id, true)?;
+        let origin_lines = self.origin_lines(id);
+        let (file, line_map) = match &origin_lines {
+            Some((origin, lines)) => (&self.files[origin.0], Some(&lines[..])),
+            None => (&self.files[id.0], None),
+        };
+        let esper_source = SourceFile {
+            path: &file.path,
+            source: &file.source,
+            line_map,
+        };
+
+        ClangCXX::compile(
+            &self.cxx,
+            &cxx_source,
+            output_path.to_str().unwrap(),
+            self.cxx_flags.clone(),
+            &esper_source,
+            self.message_format,
+            &mut self.messages,
+        )
+    }
+
+    // evaluates the module without a C++ compiler and returns the exit
+    // status of its main, a runtime error is reported like any diagnostic
+    pub fn interpret(
+        &mut self,
+        id: FileId,
+        args: Vec<String>,
+        out: impl Write + Send,
+    ) -> Result<i32, CompileError> {
+        let program = self.check(id)?;
+
+        match interp::run(&program.root, &self.module_id(id), args, self.capture, out) {
+            Ok(status) => Ok(status),
+
+            Err(diagnostic) => {
+                self.report(id, &diagnostic);
+                Err(CompileError::Runtime(self.summary()))
+            }
+        }
+    }
+}
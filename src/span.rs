@@ -0,0 +1,115 @@
+// spans are byte ranges into the source of a module, line/column pairs
+// are only computed on demand since most of them are never displayed
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn contains(&self, offset: usize) -> bool {
+        self.start <= offset && offset < self.end
+    }
+
+    // smallest span covering both spans
+    pub fn to(&self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+// 1-based line and column, columns are counted in chars
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineCol {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct LineIndex {
+    line_starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(source: &str) -> Self {
+        let mut line_starts = vec![0];
+
+        for (offset, c) in source.char_indices() {
+            if c == '\n' {
+                line_starts.push(offset + 1);
+            }
+        }
+
+        LineIndex { line_starts }
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    // byte offset of the first char on a 1-based line
+    pub fn line_start(&self, line: usize) -> usize {
+        self.line_starts[line.saturating_sub(1).min(self.line_starts.len() - 1)]
+    }
+
+    // 1-based line containing a byte offset
+    pub fn line(&self, offset: usize) -> usize {
+        match self.line_starts.binary_search(&offset) {
+            Ok(line) => line + 1,
+            Err(next_line) => next_line,
+        }
+    }
+
+    pub fn line_col(&self, source: &str, offset: usize) -> LineCol {
+        let offset = offset.min(source.len());
+        let line = self.line(offset) - 1;
+        let line_start = self.line_starts[line];
+        let column = source
+            .get(line_start..offset)
+            .map(|prefix| prefix.chars().count())
+            .unwrap_or(offset - line_start);
+
+        LineCol {
+            line: line + 1,
+            column: column + 1,
+        }
+    }
+}
+
+// names are not spanned on their own, the first whole word matching the
+// name after the keyword of the definition is taken instead
+pub fn name_span(text: &str, span: Span, name: &str) -> Span {
+    let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let source = text.get(span.start..span.end).unwrap_or("");
+    let mut search_from = 0;
+
+    while let Some(found) = source[search_from..].find(name) {
+        let start = search_from + found;
+        let end = start + name.len();
+        let before = source[..start].chars().next_back();
+        let after = source[end..].chars().next();
+
+        if !before.is_some_and(is_ident) && !after.is_some_and(is_ident) {
+            // the keyword itself is skipped, e.g. a variable named `let`
+            if !(start == 0 && matches!(name, "let" | "struct" | "type")) {
+                return Span::new(span.start + start, span.start + end);
+            }
+        }
+
+        search_from = end;
+    }
+
+    span
+}
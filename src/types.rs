@@ -0,0 +1,1217 @@
+use crate::check::Pass;
+use crate::diagnostics::{Diagnostic, ARGUMENT_COUNT, TYPE_MISMATCH};
+use crate::emit::recursive_call;
+use crate::parser::{method, BinOp, Expr, ExprKind, UnaryOp};
+use crate::span::Span;
+
+use std::collections::HashMap;
+use std::fmt;
+
+// infers the types of bindings and function returns from their values and
+// reports values that cannot be converted to the type they are used as,
+// conversions are those of the C++ output (numbers, bools and chars all
+// convert into each other), anything the checker cannot tell is unknown
+// and never reported, e.g. template parameters and most std types
+pub struct TypeChecker;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Unknown,
+    Void,
+    // int, double, bool, char, size_t, ... by their C++ name
+    Num(String),
+    String,
+    List(Box<Type>),
+    // map or unordered_map of the key and value types
+    Map(String, Box<Type>, Box<Type>),
+    Range,
+    Optional(Box<Type>),
+    Variant(Vec<Type>),
+    Fn(Vec<Type>, Box<Type>),
+    Struct(String),
+    Pointer(Box<Type>),
+    Ref(Box<Type>),
+    Nullopt,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |types: &[Type]| {
+            types
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        match self {
+            Type::Unknown => write!(f, "_"),
+            Type::Void => write!(f, "void"),
+            Type::Num(name) => write!(f, "{}", name),
+            Type::String => write!(f, "string"),
+            Type::List(item) => write!(f, "vector<{}>", item),
+            Type::Map(name, key, value) => write!(f, "{}<{}, {}>", name, key, value),
+            Type::Range => write!(f, "range"),
+            Type::Optional(inner) => write!(f, "optional<{}>", inner),
+            Type::Variant(alternatives) => write!(f, "variant<{}>", join(alternatives)),
+            Type::Fn(params, ret) => write!(f, "function<{}({})>", ret, join(params)),
+            Type::Struct(name) => write!(f, "{}", name),
+            Type::Pointer(inner) => write!(f, "{}*", inner),
+            Type::Ref(inner) => write!(f, "{}&", inner),
+            Type::Nullopt => write!(f, "nullopt_t"),
+        }
+    }
+}
+
+impl Type {
+    fn int() -> Type {
+        Type::Num("int".into())
+    }
+
+    fn bool() -> Type {
+        Type::Num("bool".into())
+    }
+
+    pub fn is_known(&self) -> bool {
+        *self != Type::Unknown
+    }
+
+    // whether a value of this type converts implicitly to the target
+    pub fn converts_to(&self, target: &Type) -> bool {
+        match (self, target) {
+            (Type::Unknown, _) | (_, Type::Unknown) => true,
+            (Type::Void, _) | (_, Type::Void) => false,
+
+            (Type::Num(_), Type::Num(_)) | (Type::String, Type::String) => true,
+            (Type::Range, Type::Range) | (Type::Nullopt, Type::Nullopt) => true,
+            (Type::List(item), Type::List(target)) => item.converts_to(target),
+            (Type::Map(name, key, value), Type::Map(target, target_key, target_value)) => {
+                name == target
+                    && [(key, target_key), (value, target_value)]
+                        .iter()
+                        .all(|(ty, target)| ty == target || !ty.is_known() || !target.is_known())
+            }
+            (Type::Struct(name), Type::Struct(target)) => name == target,
+            (Type::Pointer(inner), Type::Pointer(target)) => inner == target || !inner.is_known(),
+            (_, Type::Ref(target)) => self.converts_to(target),
+            (Type::Ref(inner), _) => inner.converts_to(target),
+
+            (Type::Fn(params, _), Type::Fn(target_params, _)) => {
+                params.len() == target_params.len()
+            }
+
+            (Type::Nullopt, Type::Optional(_)) => true,
+            (Type::Optional(inner), Type::Optional(target)) => inner.converts_to(target),
+            (_, Type::Optional(target)) => self.converts_to(target),
+
+            (Type::Variant(alternatives), Type::Variant(_)) => alternatives
+                .iter()
+                .all(|alternative| alternative.converts_to(target)),
+
+            (_, Type::Variant(alternatives)) => alternatives
+                .iter()
+                .any(|alternative| self.converts_to(alternative)),
+
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct StructInfo {
+    fields: HashMap<String, Type>,
+    methods: HashMap<String, Type>,
+}
+
+struct Checker<'a> {
+    scopes: Vec<HashMap<String, Type>>,
+    aliases: HashMap<String, &'a Expr>,
+    structs: HashMap<String, StructInfo>,
+    // scrutinee and arm types of every match, by span of the match
+    matches: HashMap<Span, (Type, Vec<Type>)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Pass for TypeChecker {
+    fn run(&self, program: &Expr, _source: &str) -> Vec<Diagnostic> {
+        let mut checker = Checker::new();
+        checker.expr(program);
+        checker.diagnostics
+    }
+}
+
+// the type of the matched value and of each arm's pattern for every
+// `match` of the program, keyed by the span of the match
+pub fn match_types(program: &Expr) -> HashMap<Span, (Type, Vec<Type>)> {
+    let mut checker = Checker::new();
+    checker.expr(program);
+    checker.matches
+}
+
+impl<'a> Checker<'a> {
+    fn new() -> Self {
+        Checker {
+            scopes: vec![HashMap::new()],
+            aliases: HashMap::new(),
+            structs: HashMap::new(),
+            matches: HashMap::new(),
+            diagnostics: vec![],
+        }
+    }
+
+    fn expr(&mut self, expr: &'a Expr) -> Type {
+        match &expr.kind {
+            ExprKind::Program(items) => {
+                for item in items {
+                    self.expr(item);
+                }
+
+                Type::Void
+            }
+
+            ExprKind::Int(_) => Type::int(),
+            ExprKind::Float(_) => Type::Num("double".into()),
+            ExprKind::Bool(_) => Type::bool(),
+            ExprKind::Char(_) => Type::Num("char".into()),
+            ExprKind::String(_) => Type::String,
+            ExprKind::Pass => Type::Void,
+            ExprKind::Var(name) => self.lookup(name),
+
+            ExprKind::List(items) => {
+                let types = items.iter().map(|item| self.expr(item)).collect::<Vec<_>>();
+
+                match types.split_first() {
+                    Some((first, rest)) if rest.iter().all(|ty| ty == first) => {
+                        Type::List(Box::new(first.clone()))
+                    }
+
+                    _ => Type::List(Box::new(Type::Unknown)),
+                }
+            }
+
+            ExprKind::Record(entries) => self.record(entries, None),
+
+            ExprKind::Construct(name, fields) => match &fields.kind {
+                ExprKind::Record(entries) if self.structs.contains_key(name) => {
+                    self.record(entries, Some(&Type::Struct(name.clone())))
+                }
+                _ => {
+                    self.expr(fields);
+                    Type::Unknown
+                }
+            },
+
+            ExprKind::Range(start, end) => {
+                for bound in [start, end] {
+                    let ty = self.expr(bound);
+                    self.expect(&ty, &Type::int(), bound.span);
+                }
+
+                Type::Range
+            }
+
+            ExprKind::Unary(operand, op) => {
+                let ty = self.expr(operand);
+
+                match (op, ty) {
+                    (UnaryOp::Ref, ty) => Type::Pointer(Box::new(ty)),
+                    (UnaryOp::Deref, Type::Pointer(inner)) => *inner,
+                    (UnaryOp::Deref, _) => Type::Unknown,
+                    (UnaryOp::BitNot, ty) => self.integral(ty, operand.span),
+                }
+            }
+
+            ExprKind::Bin(lhs, op, rhs) => {
+                let lhs_ty = self.expr(lhs);
+                let rhs_ty = self.expr(rhs);
+
+                self.arithmetic(op, lhs_ty, rhs_ty, expr.span)
+            }
+
+            ExprKind::Compare(lhs, _, rhs) => {
+                let lhs_ty = self.expr(lhs);
+                let rhs_ty = self.expr(rhs);
+
+                if let (Type::String, Type::Num(_)) | (Type::Num(_), Type::String) =
+                    (&lhs_ty, &rhs_ty)
+                {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            format!("cannot compare `{}` and `{}`", lhs_ty, rhs_ty),
+                            expr.span,
+                        )
+                        .with_code(TYPE_MISMATCH),
+                    );
+                }
+
+                Type::bool()
+            }
+
+            ExprKind::Bit(lhs, _, rhs) => {
+                let lhs_ty = self.expr(lhs);
+                let rhs_ty = self.expr(rhs);
+
+                self.integral(lhs_ty, lhs.span);
+                self.integral(rhs_ty, rhs.span)
+            }
+
+            // a redefinition keeps the first binding, the resolver reports it
+            ExprKind::Let(name, _, value) => {
+                let binding = self.binding(name);
+
+                let ty = match &value.kind {
+                    ExprKind::Fn(params, body) => {
+                        self.local_recursion(name, params, body);
+                        self.function(binding, params, body, None)
+                    }
+
+                    _ => self.expr(value),
+                };
+
+                if ty == Type::Void {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            format!("`{}` is bound to a value of type `void`", name),
+                            value.span,
+                        )
+                        .with_code(TYPE_MISMATCH)
+                        .with_label("this expression has no value"),
+                    );
+                }
+
+                if let Some(name) = binding {
+                    self.define(name, ty);
+                }
+
+                Type::Void
+            }
+
+            ExprKind::TypedLet(name, _, ty, value) => {
+                let binding = self.binding(name);
+                let declared = self.resolve(ty);
+
+                let ty = match &value.kind {
+                    ExprKind::Fn(params, body) => {
+                        self.function(binding, params, body, Some(declared))
+                    }
+
+                    _ => {
+                        let found = self.value(value, Some(&declared));
+                        self.expect(&found, &declared, value.span);
+
+                        match declared {
+                            Type::Unknown => found,
+                            declared => declared,
+                        }
+                    }
+                };
+
+                if let Some(name) = binding {
+                    self.define(name, ty);
+                }
+
+                Type::Void
+            }
+
+            ExprKind::Assign(lhs, rhs) => {
+                let found = self.expr(rhs);
+
+                if let ExprKind::Var(name) = &lhs.kind {
+                    let expected = self.lookup(name);
+                    self.expect(&found, &expected, rhs.span);
+                }
+
+                Type::Void
+            }
+
+            ExprKind::If(cond, then_body, else_body) => {
+                self.if_expr(cond, then_body, else_body.as_deref(), None)
+            }
+
+            ExprKind::Loop(pattern, iter, body) => {
+                let item = match self.expr(iter) {
+                    Type::Range => Type::int(),
+                    Type::List(item) => *item,
+                    Type::String => Type::Num("char".into()),
+                    _ => Type::Unknown,
+                };
+
+                let bindings = match &pattern.kind {
+                    ExprKind::Var(name) => vec![(name.clone(), item)],
+
+                    ExprKind::List(names) => names
+                        .iter()
+                        .filter_map(|name| match &name.kind {
+                            ExprKind::Var(name) => Some((name.clone(), Type::Unknown)),
+                            _ => None,
+                        })
+                        .collect(),
+
+                    _ => vec![],
+                };
+
+                self.block(body, bindings, None);
+                Type::Void
+            }
+
+            ExprKind::Match(cond, cases) => self.match_expr(expr.span, cond, cases, None),
+
+            ExprKind::Fn(params, body) => self.function(None, params, body, None),
+
+            ExprKind::Call(callee, args) => {
+                let arg_types = args.iter().map(|arg| self.expr(arg)).collect::<Vec<_>>();
+
+                if let ExprKind::Var(name) = &callee.kind {
+                    if !self.is_bound(name) && self.structs.contains_key(name) {
+                        return Type::Struct(name.clone());
+                    }
+                }
+
+                let callee_ty = self.expr(callee);
+                self.call(callee_ty, args, &arg_types, expr.span)
+            }
+
+            ExprKind::TypedCall(callee, generics, args) => {
+                let arg_types = args.iter().map(|arg| self.expr(arg)).collect::<Vec<_>>();
+
+                match (&callee.kind, &generics[..]) {
+                    (ExprKind::Var(name), [item]) if name == "vector" && !self.is_bound(name) => {
+                        Type::List(Box::new(self.resolve(item)))
+                    }
+
+                    (ExprKind::Var(name), [inner])
+                        if name == "optional" && !self.is_bound(name) =>
+                    {
+                        Type::Optional(Box::new(self.resolve(inner)))
+                    }
+
+                    _ => {
+                        let callee_ty = self.expr(callee);
+                        self.call(callee_ty, args, &arg_types, expr.span)
+                    }
+                }
+            }
+
+            ExprKind::Member(members) => {
+                let mut chain = vec![];
+                flatten_members(members, &mut chain);
+
+                self.member(&chain)
+            }
+
+            ExprKind::Struct(name, _, entries) => {
+                let mut info = StructInfo::default();
+
+                for (entry, ty) in entries {
+                    if method(ty).is_none() {
+                        info.fields.insert(entry.clone(), self.resolve(ty));
+                    }
+                }
+
+                self.structs.insert(name.clone(), info.clone());
+
+                // methods see the fields and each other without `this.`,
+                // and the instance as `self`
+                let mut scope = info.fields.clone();
+                scope.insert("self".into(), Type::Struct(name.clone()));
+                self.scopes.push(scope);
+
+                for (entry, value) in entries {
+                    if let Some((params, body, return_type)) = method(value) {
+                        let declared = return_type.map(|ty| self.resolve(ty));
+                        let ty = self.function(Some(entry), params, body, declared);
+                        info.methods.insert(entry.clone(), ty);
+                    }
+                }
+
+                self.scopes.pop();
+                self.structs.insert(name.clone(), info);
+                Type::Void
+            }
+
+            ExprKind::TypeAlias(name, _, rhs) => {
+                self.aliases.insert(name.clone(), rhs);
+                Type::Void
+            }
+
+            ExprKind::Directive(_, inner) => self.expr(inner),
+
+            _ => Type::Unknown,
+        }
+    }
+
+    // the type of the last expression of a block, if it has a value
+    fn block(
+        &mut self,
+        body: &'a [Expr],
+        bindings: Vec<(String, Type)>,
+        target: Option<&Type>,
+    ) -> Type {
+        self.scopes.push(bindings.into_iter().collect());
+
+        let mut ty = Type::Void;
+
+        if let Some((last, rest)) = body.split_last() {
+            for expr in rest {
+                self.expr(expr);
+            }
+
+            ty = self.value(last, target);
+
+            if !last.is_value() {
+                ty = Type::Void;
+            }
+        }
+
+        self.scopes.pop();
+        ty
+    }
+
+    // an `if` has a value when both of its branches have one, of types
+    // converting into each other as the operands of a conditional must
+    fn if_expr(
+        &mut self,
+        cond: &'a Expr,
+        then_body: &'a [Expr],
+        else_body: Option<&'a [Expr]>,
+        target: Option<&Type>,
+    ) -> Type {
+        self.expr(cond);
+        let then_ty = self.block(then_body, vec![], target);
+
+        let Some(else_body) = else_body else {
+            return Type::Void;
+        };
+
+        let else_ty = self.block(else_body, vec![], target);
+
+        match (then_ty, else_ty) {
+            (Type::Void, _) | (_, Type::Void) => Type::Void,
+            (Type::Num(a), Type::Num(b)) => Type::Num(promote(&a, &b).into()),
+            (then_ty, else_ty)
+                if then_ty.converts_to(&else_ty) && else_ty.converts_to(&then_ty) =>
+            {
+                if then_ty.is_known() {
+                    then_ty
+                } else {
+                    else_ty
+                }
+            }
+
+            (then_ty, else_ty) => {
+                let span = else_body.last().map_or(cond.span, |last| last.span);
+
+                self.diagnostics.push(
+                    Diagnostic::error("`if` and `else` have incompatible types", span)
+                        .with_code(TYPE_MISMATCH)
+                        .with_label(format!("expected `{}`, found `{}`", then_ty, else_ty)),
+                );
+
+                Type::Unknown
+            }
+        }
+    }
+
+    // arms see the matched value as `_` of the type they match, the match
+    // has a value when all arms have one of the same type
+    fn match_expr(
+        &mut self,
+        span: Span,
+        cond: &'a Expr,
+        cases: &'a [(String, Vec<Expr>)],
+        target: Option<&Type>,
+    ) -> Type {
+        let scrutinee = self.expr(cond);
+        let mut arms = vec![];
+        let mut ty: Option<Type> = None;
+
+        for (pattern, body) in cases {
+            let arm = self.named_type(pattern);
+            let found = self.block(body, vec![("_".into(), arm.clone())], target);
+
+            ty = match ty {
+                None => Some(found),
+                Some(Type::Void) => Some(Type::Void),
+                Some(_) if found == Type::Void => Some(Type::Void),
+                Some(ty) if ty == found => Some(ty),
+                Some(_) => Some(Type::Unknown),
+            };
+
+            arms.push(arm);
+        }
+
+        self.matches.insert(span, (scrutinee, arms));
+        ty.unwrap_or(Type::Void)
+    }
+
+    // the type of a function, its return type is inferred from the last
+    // expression when not declared (statements return nothing)
+    fn function(
+        &mut self,
+        name: Option<&str>,
+        params: &'a [(String, Option<Expr>)],
+        body: &'a [Expr],
+        declared: Option<Type>,
+    ) -> Type {
+        let param_types = params
+            .iter()
+            .map(|(_, ty)| ty.as_ref().map_or(Type::Unknown, |ty| self.resolve(ty)))
+            .collect::<Vec<_>>();
+
+        let ret = declared.clone().unwrap_or(Type::Unknown);
+
+        // recursive calls see the declared return type, if any
+        if let Some(name) = name {
+            self.define(name, Type::Fn(param_types.clone(), Box::new(ret)));
+        }
+
+        self.scopes.push(
+            params
+                .iter()
+                .map(|(param, _)| param.clone())
+                .zip(param_types.clone())
+                .collect(),
+        );
+
+        let mut found = Type::Void;
+
+        if let Some((last, rest)) = body.split_last() {
+            for expr in rest {
+                self.expr(expr);
+            }
+
+            found = self.returned(last, declared.as_ref());
+        }
+
+        let last = body.last();
+
+        if !last.is_some_and(Expr::is_value) {
+            found = Type::Void;
+        }
+
+        self.scopes.pop();
+
+        let ret = match (declared, last) {
+            (Some(declared), Some(last)) if last.is_value() => {
+                self.expect(&found, &declared, last.span);
+                declared
+            }
+
+            (Some(declared), _) => declared,
+            (None, _) => found,
+        };
+
+        Type::Fn(param_types, Box::new(ret))
+    }
+
+    // the last expression of a function, a last `if` or `match` returns
+    // from each of its branches, these only need to convert to a declared
+    // return type but must be of one type for C++ to deduce it otherwise
+    fn returned(&mut self, expr: &'a Expr, declared: Option<&Type>) -> Type {
+        match &expr.kind {
+            ExprKind::If(cond, then_body, Some(else_body)) => {
+                self.expr(cond);
+                let branches = vec![
+                    self.returned_block(then_body, vec![], declared),
+                    self.returned_block(else_body, vec![], declared),
+                ];
+
+                self.deduced(branches, declared)
+            }
+
+            ExprKind::Match(cond, cases) => {
+                let scrutinee = self.expr(cond);
+                let mut arms = vec![];
+                let mut branches = vec![];
+
+                for (pattern, body) in cases {
+                    let arm = self.named_type(pattern);
+                    let bindings = vec![("_".into(), arm.clone())];
+
+                    branches.push(self.returned_block(body, bindings, declared));
+                    arms.push(arm);
+                }
+
+                self.matches.insert(expr.span, (scrutinee, arms));
+                self.deduced(branches, declared)
+            }
+
+            _ => self.value(expr, declared),
+        }
+    }
+
+    // the type returned from a branch and the span of its last expression
+    fn returned_block(
+        &mut self,
+        body: &'a [Expr],
+        bindings: Vec<(String, Type)>,
+        declared: Option<&Type>,
+    ) -> (Type, Span) {
+        self.scopes.push(bindings.into_iter().collect());
+
+        let mut found = (Type::Void, Span::default());
+
+        if let Some((last, rest)) = body.split_last() {
+            for expr in rest {
+                self.expr(expr);
+            }
+
+            found = match last.is_value() {
+                true => (self.returned(last, declared), last.span),
+                false => {
+                    self.expr(last);
+                    (Type::Void, last.span)
+                }
+            };
+
+            if let Some(declared) = declared {
+                self.expect(&found.0, declared, last.span);
+            }
+        }
+
+        self.scopes.pop();
+        found
+    }
+
+    // the return type of branches returned from the same function, a
+    // branch without a value returns nothing
+    fn deduced(&mut self, branches: Vec<(Type, Span)>, declared: Option<&Type>) -> Type {
+        if let Some(declared) = declared {
+            return declared.clone();
+        }
+
+        let mut deduced: Option<Type> = None;
+
+        for (found, span) in branches {
+            let expected = match &deduced {
+                None => {
+                    deduced = Some(found);
+                    continue;
+                }
+
+                Some(expected) => expected,
+            };
+
+            if *expected == Type::Void || found == Type::Void {
+                deduced = Some(Type::Void);
+            } else if !expected.is_known() {
+                deduced = Some(found);
+            } else if found.is_known() && !same_type(expected, &found) {
+                self.diagnostics.push(
+                    Diagnostic::error("returned values have different types", span)
+                        .with_code(TYPE_MISMATCH)
+                        .with_label(format!("expected `{}`, found `{}`", expected, found))
+                        .with_note(
+                            "the return type is deduced from each branch, declare it to convert them",
+                        ),
+                );
+
+                return Type::Unknown;
+            }
+        }
+
+        deduced.unwrap_or(Type::Void)
+    }
+
+    // a value for a binding or return of the type, records take the type
+    // they are declared as
+    fn value(&mut self, expr: &'a Expr, target: Option<&Type>) -> Type {
+        match &expr.kind {
+            ExprKind::Record(entries) => self.record(entries, target),
+            ExprKind::If(cond, then_body, else_body) => {
+                self.if_expr(cond, then_body, else_body.as_deref(), target)
+            }
+            ExprKind::Match(cond, cases) => self.match_expr(expr.span, cond, cases, target),
+            _ => self.expr(expr),
+        }
+    }
+
+    // a record is an aggregate of a struct's fields or the entries of a
+    // map, untyped it is an unordered_map of its key and value types
+    fn record(&mut self, entries: &'a [Vec<Expr>], target: Option<&Type>) -> Type {
+        let entries = entries
+            .iter()
+            .filter_map(|entry| match &entry[..] {
+                [key, value] => Some((key, value)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        match target {
+            Some(Type::Struct(name)) => {
+                let info = self.structs.get(name).cloned().unwrap_or_default();
+                let mut given: Vec<&str> = vec![];
+
+                for (key, value) in entries {
+                    let ExprKind::Var(field) = &key.kind else {
+                        self.diagnostics.push(
+                            Diagnostic::error(format!("expected a field of `{}`", name), key.span)
+                                .with_code(TYPE_MISMATCH)
+                                .with_label("not a field name"),
+                        );
+                        self.expr(value);
+                        continue;
+                    };
+
+                    if !info.fields.contains_key(field) {
+                        self.diagnostics.push(
+                            Diagnostic::error(
+                                format!("no field `{}` on struct `{}`", field, name),
+                                key.span,
+                            )
+                            .with_code(TYPE_MISMATCH)
+                            .with_label("unknown field"),
+                        );
+                        self.expr(value);
+                        continue;
+                    }
+
+                    // fields are passed to the constructor in declaration
+                    // order whatever their order here, but only once
+                    if given.contains(&field.as_str()) {
+                        self.diagnostics.push(
+                            Diagnostic::error(
+                                format!("field `{}` is given more than once", field),
+                                key.span,
+                            )
+                            .with_code(TYPE_MISMATCH)
+                            .with_label("already given"),
+                        );
+                    }
+
+                    given.push(field);
+
+                    let expected = info.fields[field].clone();
+                    let found = self.value(value, Some(&expected));
+                    self.expect(&found, &expected, value.span);
+                }
+
+                Type::Struct(name.clone())
+            }
+
+            Some(Type::Map(name, key_type, value_type)) => {
+                for (key, value) in entries {
+                    let found = self.record_key(key);
+                    self.expect(&found, key_type, key.span);
+
+                    let found = self.value(value, Some(value_type));
+                    self.expect(&found, value_type, value.span);
+                }
+
+                Type::Map(name.clone(), key_type.clone(), value_type.clone())
+            }
+
+            // of a type the checker cannot tell, e.g. an anonymous record
+            Some(Type::Unknown) => {
+                for (_, value) in entries {
+                    self.expr(value);
+                }
+
+                Type::Unknown
+            }
+
+            _ => {
+                let mut key_type = Type::Unknown;
+                let mut value_type = Type::Unknown;
+
+                for (key, value) in entries {
+                    let found = self.record_key(key);
+
+                    if key_type.is_known() && found != key_type {
+                        self.diagnostics.push(
+                            Diagnostic::error("record keys of different types", key.span)
+                                .with_code(TYPE_MISMATCH)
+                                .with_label(format!("expected `{}`, found `{}`", key_type, found))
+                                .with_note("declare the record as a map to convert its keys"),
+                        );
+                    } else {
+                        key_type = found;
+                    }
+
+                    let found = self.value(value, None);
+
+                    match (&value_type, &found) {
+                        (Type::Unknown, _) => value_type = found,
+                        (Type::Num(_), Type::Num(name)) if name == "double" => value_type = found,
+                        _ => self.expect(&found, &value_type.clone(), value.span),
+                    }
+                }
+
+                Type::Map(
+                    "unordered_map".into(),
+                    Box::new(key_type),
+                    Box::new(value_type),
+                )
+            }
+        }
+    }
+
+    fn record_key(&self, key: &Expr) -> Type {
+        match key.kind {
+            ExprKind::Int(_) => Type::int(),
+            _ => Type::String,
+        }
+    }
+
+    fn call(&mut self, callee: Type, args: &[Expr], arg_types: &[Type], span: Span) -> Type {
+        let Type::Fn(params, ret) = callee else {
+            return Type::Unknown;
+        };
+
+        if params.len() != args.len() {
+            self.diagnostics.push(
+                Diagnostic::error(
+                    format!(
+                        "this function takes {} argument{} but {} {} supplied",
+                        params.len(),
+                        if params.len() == 1 { "" } else { "s" },
+                        args.len(),
+                        if args.len() == 1 { "was" } else { "were" }
+                    ),
+                    span,
+                )
+                .with_code(ARGUMENT_COUNT),
+            );
+
+            return *ret;
+        }
+
+        for ((param, arg), arg_ty) in params.iter().zip(args).zip(arg_types) {
+            self.expect(arg_ty, param, arg.span);
+        }
+
+        *ret
+    }
+
+    // `a.f(x)` on structs with known methods, esper.print/esper.type_of
+    // and the std::vector members the interpreter knows about
+    fn member(&mut self, chain: &[&'a Expr]) -> Type {
+        let Some((first, rest)) = chain.split_first() else {
+            return Type::Unknown;
+        };
+
+        if matches!(&first.kind, ExprKind::Var(name) if name == "esper" && !self.is_bound(name)) {
+            for member in rest {
+                if let ExprKind::Call(_, args) = &member.kind {
+                    for arg in args {
+                        self.expr(arg);
+                    }
+                }
+            }
+
+            return match rest {
+                [call] => match &call.kind {
+                    ExprKind::Call(callee, _) => match &callee.kind {
+                        ExprKind::Var(name) if name == "print" => Type::Void,
+                        ExprKind::Var(name) if name == "type_of" => Type::String,
+                        _ => Type::Unknown,
+                    },
+                    _ => Type::Unknown,
+                },
+                _ => Type::Unknown,
+            };
+        }
+
+        let mut current = self.expr(first);
+
+        for member in rest {
+            current = match &member.kind {
+                ExprKind::Var(field) => match &current {
+                    Type::Struct(name) => self
+                        .structs
+                        .get(name)
+                        .and_then(|info| info.fields.get(field).cloned())
+                        .unwrap_or(Type::Unknown),
+                    _ => Type::Unknown,
+                },
+
+                ExprKind::Call(method, args) | ExprKind::TypedCall(method, _, args) => {
+                    let arg_types = args.iter().map(|arg| self.expr(arg)).collect::<Vec<_>>();
+
+                    let ExprKind::Var(method) = &method.kind else {
+                        return Type::Unknown;
+                    };
+
+                    match (&current, method.as_str()) {
+                        (Type::Struct(name), _) => {
+                            let method_ty = self
+                                .structs
+                                .get(name)
+                                .and_then(|info| info.methods.get(method).cloned())
+                                .unwrap_or(Type::Unknown);
+
+                            self.call(method_ty, args, &arg_types, member.span)
+                        }
+
+                        (Type::List(_) | Type::String, "size") => Type::Num("size_t".into()),
+                        (Type::List(_) | Type::String, "empty") => Type::bool(),
+                        (Type::List(_), "push_back") => Type::Void,
+                        (Type::Map(_, _, _), "size") => Type::Num("size_t".into()),
+                        (Type::Map(_, _, _), "empty" | "contains") => Type::bool(),
+                        (Type::Map(_, _, value), "at") => *value.clone(),
+                        _ => Type::Unknown,
+                    }
+                }
+
+                _ => Type::Unknown,
+            };
+        }
+
+        current
+    }
+
+    fn arithmetic(&mut self, op: &BinOp, lhs: Type, rhs: Type, span: Span) -> Type {
+        let symbol = match op {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+        };
+
+        match (&lhs, &rhs) {
+            (Type::Unknown, _) | (_, Type::Unknown) => Type::Unknown,
+            (Type::Num(a), Type::Num(b)) => Type::Num(promote(a, b).into()),
+            (Type::String, Type::String) if *op == BinOp::Add => Type::String,
+            (Type::String, Type::Num(c)) | (Type::Num(c), Type::String)
+                if *op == BinOp::Add && c == "char" =>
+            {
+                Type::String
+            }
+
+            (Type::Pointer(_), Type::Num(_)) if matches!(op, BinOp::Add | BinOp::Sub) => lhs,
+
+            (Type::Struct(_) | Type::Variant(_) | Type::Optional(_) | Type::Ref(_), _)
+            | (_, Type::Struct(_) | Type::Variant(_) | Type::Optional(_) | Type::Ref(_)) => {
+                Type::Unknown
+            }
+
+            _ => {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        format!("cannot apply `{}` to `{}` and `{}`", symbol, lhs, rhs),
+                        span,
+                    )
+                    .with_code(TYPE_MISMATCH),
+                );
+
+                Type::Unknown
+            }
+        }
+    }
+
+    // operands of bitwise operators
+    fn integral(&mut self, ty: Type, span: Span) -> Type {
+        match ty {
+            Type::Num(name) if !matches!(name.as_str(), "float" | "double") => Type::Num(name),
+            Type::Unknown | Type::Struct(_) | Type::Variant(_) | Type::Ref(_) => Type::Unknown,
+
+            ty => {
+                self.diagnostics.push(
+                    Diagnostic::error(format!("expected an integer, found `{}`", ty), span)
+                        .with_code(TYPE_MISMATCH),
+                );
+
+                Type::Unknown
+            }
+        }
+    }
+
+    fn expect(&mut self, found: &Type, expected: &Type, span: Span) {
+        if !found.converts_to(expected) {
+            self.diagnostics.push(
+                Diagnostic::error("mismatched types", span)
+                    .with_code(TYPE_MISMATCH)
+                    .with_label(format!("expected `{}`, found `{}`", expected, found)),
+            );
+        }
+    }
+
+    // a type expression as written in an annotation or alias
+    fn resolve(&self, ty: &Expr) -> Type {
+        match &ty.kind {
+            ExprKind::TypedSymbol(name) => self.named_type(name),
+
+            ExprKind::TypedSymbolGeneric(name, args) => match (name.as_str(), &args[..]) {
+                ("vector", [item]) => Type::List(Box::new(self.resolve(item))),
+                ("map" | "unordered_map", [key, value]) => Type::Map(
+                    name.clone(),
+                    Box::new(self.resolve(key)),
+                    Box::new(self.resolve(value)),
+                ),
+                ("optional", [inner]) => Type::Optional(Box::new(self.resolve(inner))),
+                ("variant", alternatives) => {
+                    Type::Variant(alternatives.iter().map(|alt| self.resolve(alt)).collect())
+                }
+                // the fields of generic structs are left to C++ with their parameters
+                (name, _) if self.structs.contains_key(name) => Type::Struct(name.into()),
+                _ => Type::Unknown,
+            },
+
+            ExprKind::TypedOptional(inner) => Type::Optional(Box::new(self.resolve(inner))),
+
+            ExprKind::TypedVariant(_, _) => {
+                let mut alternatives = vec![];
+                self.variant_alternatives(ty, &mut alternatives);
+                Type::Variant(alternatives)
+            }
+
+            ExprKind::TypedUnary(inner) => match &inner.kind {
+                ExprKind::Unary(inner, UnaryOp::Ref) => Type::Ref(Box::new(self.resolve(inner))),
+                ExprKind::Unary(inner, UnaryOp::Deref) => {
+                    Type::Pointer(Box::new(self.resolve(inner)))
+                }
+                _ => Type::Unknown,
+            },
+
+            // decltype of the literal
+            ExprKind::TypedLiteral(literal) => match literal.kind {
+                ExprKind::Int(_) => Type::int(),
+                ExprKind::Float(_) => Type::Num("double".into()),
+                ExprKind::Bool(_) => Type::bool(),
+                _ => Type::Unknown,
+            },
+
+            ExprKind::TypedFn(function) => match &function.kind {
+                ExprKind::Fn(params, body) => Type::Fn(
+                    params
+                        .iter()
+                        .map(|(_, ty)| ty.as_ref().map_or(Type::Unknown, |ty| self.resolve(ty)))
+                        .collect(),
+                    Box::new(body.last().map_or(Type::Unknown, |ret| self.resolve(ret))),
+                ),
+                _ => Type::Unknown,
+            },
+
+            // records of types and qualified names are left to C++
+            _ => Type::Unknown,
+        }
+    }
+
+    fn variant_alternatives(&self, ty: &Expr, alternatives: &mut Vec<Type>) {
+        match &ty.kind {
+            ExprKind::TypedVariant(lhs, rhs) => {
+                self.variant_alternatives(lhs, alternatives);
+                self.variant_alternatives(rhs, alternatives);
+            }
+
+            _ => alternatives.push(self.resolve(ty)),
+        }
+    }
+
+    fn named_type(&self, name: &str) -> Type {
+        match name {
+            "string" => Type::String,
+            "void" => Type::Void,
+            "vector" => Type::List(Box::new(Type::Unknown)),
+            name if is_numeric(name) => Type::Num(name.into()),
+            name if self.structs.contains_key(name) => Type::Struct(name.into()),
+
+            // generic aliases need their arguments
+            name => match self.aliases.get(name) {
+                Some(alias) => self.resolve(alias),
+                None => Type::Unknown,
+            },
+        }
+    }
+
+    fn define(&mut self, name: &str, ty: Type) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.into(), ty);
+        }
+    }
+
+    // the name to bind a definition to, none when already defined in scope
+    // functions within functions are lambdas, a recursive one is called
+    // through a forwarding lambda whose return type cannot be deduced
+    // before that of the function itself
+    fn local_recursion(&mut self, name: &str, params: &[(String, Option<Expr>)], body: &[Expr]) {
+        let Some(call) = recursive_call(name, body) else {
+            return;
+        };
+
+        if self.scopes.len() > 1 && params.iter().all(|(param, _)| param != name) {
+            self.diagnostics.push(
+                Diagnostic::error(
+                    format!("recursive function `{}` needs a declared return type", name),
+                    call,
+                )
+                .with_code(TYPE_MISMATCH)
+                .with_label("called before its return type is known")
+                .with_note(format!(
+                    "functions within functions can only recurse with a return type, e.g. `let {}: int = |...| ... end`",
+                    name
+                )),
+            );
+        }
+    }
+
+    fn binding<'n>(&self, name: &'n str) -> Option<&'n str> {
+        match self.scopes.last() {
+            Some(scope) if scope.contains_key(name) => None,
+            _ => Some(name),
+        }
+    }
+
+    fn is_bound(&self, name: &str) -> bool {
+        self.scopes.iter().any(|scope| scope.contains_key(name))
+    }
+
+    fn lookup(&self, name: &str) -> Type {
+        match self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+            Some(ty) => ty.clone(),
+            None if name == "nullopt" => Type::Nullopt,
+            None => Type::Unknown,
+        }
+    }
+}
+
+fn is_numeric(name: &str) -> bool {
+    matches!(
+        name,
+        "int"
+            | "long"
+            | "short"
+            | "unsigned"
+            | "size_t"
+            | "int8_t"
+            | "int16_t"
+            | "int32_t"
+            | "int64_t"
+            | "uint8_t"
+            | "uint16_t"
+            | "uint32_t"
+            | "uint64_t"
+            | "float"
+            | "double"
+            | "bool"
+            | "char"
+    )
+}
+
+// whether `auto` deduces one type from values of both, numbers of
+// different types convert but are not the same
+fn same_type(a: &Type, b: &Type) -> bool {
+    match (a, b) {
+        (Type::Num(a), Type::Num(b)) => a == b,
+        (a, b) => a.converts_to(b) && b.converts_to(a),
+    }
+}
+
+// usual arithmetic conversions, simplified
+fn promote<'n>(a: &'n str, b: &'n str) -> &'n str {
+    match (a, b) {
+        ("double", _) | (_, "double") => "double",
+        ("float", _) | (_, "float") => "float",
+        _ if a == b => a,
+        _ => "int",
+    }
+}
+
+fn flatten_members<'a>(members: &'a [Expr], chain: &mut Vec<&'a Expr>) {
+    for member in members {
+        match &member.kind {
+            ExprKind::Member(inner) => flatten_members(inner, chain),
+            _ => chain.push(member),
+        }
+    }
+}
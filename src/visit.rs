@@ -27,31 +27,35 @@ impl Visitor for Expr {
     ) {
         callback(ctx, self);
 
-        match self {
-            Expr::Program(exprs) => {
+        match &self.kind {
+            ExprKind::Program(exprs) => {
                 for expr in exprs {
                     expr.visit(ctx, callback);
                 }
             }
 
-            Expr::Let(_, expr) => expr.visit(ctx, callback),
+            ExprKind::Let(_, _, expr) => expr.visit(ctx, callback),
 
-            Expr::Assign(lhs, rhs) => {
+            ExprKind::Assign(lhs, rhs) => {
                 lhs.visit(ctx, callback);
                 rhs.visit(ctx, callback);
             }
 
-            Expr::Var(_) => {}
+            ExprKind::Var(_) => {}
 
-            Expr::Int(_) | Expr::Float(_) | Expr::Bool(_) | Expr::Char(_) | Expr::String(_) => {}
+            ExprKind::Int(_)
+            | ExprKind::Float(_)
+            | ExprKind::Bool(_)
+            | ExprKind::Char(_)
+            | ExprKind::String(_) => {}
 
-            Expr::List(exprs) => {
+            ExprKind::List(exprs) => {
                 for expr in exprs {
                     expr.visit(ctx, callback);
                 }
             }
 
-            Expr::Record(entries) => {
+            ExprKind::Record(entries) => {
                 for entry in entries {
                     for expr in entry {
                         expr.visit(ctx, callback);
@@ -59,31 +63,31 @@ impl Visitor for Expr {
                 }
             }
 
-            Expr::Range(start, end) => {
+            ExprKind::Range(start, end) => {
                 start.visit(ctx, callback);
                 end.visit(ctx, callback);
             }
 
-            Expr::Bin(lhs, _, rhs) => {
+            ExprKind::Bin(lhs, _, rhs) => {
                 lhs.visit(ctx, callback);
                 rhs.visit(ctx, callback);
             }
 
-            Expr::Compare(lhs, _, rhs) => {
+            ExprKind::Compare(lhs, _, rhs) => {
                 lhs.visit(ctx, callback);
                 rhs.visit(ctx, callback);
             }
 
-            Expr::Bit(lhs, _, rhs) => {
+            ExprKind::Bit(lhs, _, rhs) => {
                 lhs.visit(ctx, callback);
                 rhs.visit(ctx, callback);
             }
 
-            Expr::Unary(expr, _) => {
+            ExprKind::Unary(expr, _) => {
                 expr.visit(ctx, callback);
             }
 
-            Expr::If(cond, then_body, else_body) => {
+            ExprKind::If(cond, then_body, else_body) => {
                 cond.visit(ctx, callback);
 
                 for expr in then_body {
@@ -97,7 +101,7 @@ impl Visitor for Expr {
                 }
             }
 
-            Expr::Loop(var, iter, body) => {
+            ExprKind::Loop(var, iter, body) => {
                 var.visit(ctx, callback);
                 iter.visit(ctx, callback);
 
@@ -106,29 +110,29 @@ impl Visitor for Expr {
                 }
             }
 
-            Expr::Match(cond, cases) => {
+            ExprKind::Match(cond, cases) => {
                 cond.visit(ctx, callback);
 
-                for (pat, body) in cases {
+                for (_, body) in cases {
                     for expr in body {
                         expr.visit(ctx, callback);
                     }
                 }
             }
 
-            Expr::Fn(_, body) => {
+            ExprKind::Fn(_, body) => {
                 for expr in body {
                     expr.visit(ctx, callback);
                 }
             }
 
-            Expr::Member(exprs) => {
+            ExprKind::Member(exprs) => {
                 for expr in exprs {
                     expr.visit(ctx, callback);
                 }
             }
 
-            Expr::Call(callee, args) => {
+            ExprKind::Call(callee, args) => {
                 callee.visit(ctx, callback);
 
                 for arg in args {
@@ -136,42 +140,44 @@ impl Visitor for Expr {
                 }
             }
 
-            Expr::Struct(_, entries) => {
+            ExprKind::Struct(_, _, entries) => {
                 for (_, expr) in entries {
                     expr.visit(ctx, callback);
                 }
             }
 
-            Expr::Pass => {}
+            ExprKind::Construct(_, fields) => fields.visit(ctx, callback),
 
-            Expr::TypedSymbol(_) => {}
+            ExprKind::Pass => {}
 
-            Expr::TypedSymbolGeneric(_, exprs) => {
+            ExprKind::TypedSymbol(_) => {}
+
+            ExprKind::TypedSymbolGeneric(_, exprs) => {
                 for expr in exprs {
                     expr.visit(ctx, callback);
                 }
             }
 
-            Expr::TypedUnary(expr) => expr.visit(ctx, callback),
-            Expr::TypedLiteral(expr) => expr.visit(ctx, callback),
+            ExprKind::TypedUnary(expr) => expr.visit(ctx, callback),
+            ExprKind::TypedLiteral(expr) => expr.visit(ctx, callback),
 
-            Expr::TypedMember(expr) => expr.visit(ctx, callback),
-            Expr::TypedOptional(expr) => expr.visit(ctx, callback),
+            ExprKind::TypedMember(expr) => expr.visit(ctx, callback),
+            ExprKind::TypedOptional(expr) => expr.visit(ctx, callback),
 
-            Expr::TypedVariant(lhs, rhs) => {
+            ExprKind::TypedVariant(lhs, rhs) => {
                 lhs.visit(ctx, callback);
                 rhs.visit(ctx, callback);
             }
 
-            Expr::TypedFn(expr) => expr.visit(ctx, callback),
+            ExprKind::TypedFn(expr) => expr.visit(ctx, callback),
 
-            Expr::TypedLet(_, ty, expr) => {
+            ExprKind::TypedLet(_, _, ty, expr) => {
                 ty.visit(ctx, callback);
                 expr.visit(ctx, callback);
             }
 
-            Expr::TypedRecord(record) => {
-                if let Expr::Record(record) = *record.to_owned() {
+            ExprKind::TypedRecord(record) => {
+                if let ExprKind::Record(record) = &record.kind {
                     for entry in record.clone() {
                         for expr in entry {
                             expr.visit(ctx, callback);
@@ -180,16 +186,16 @@ impl Visitor for Expr {
                 }
             }
 
-            Expr::TypeAlias(_, _, expr) => expr.visit(ctx, callback),
+            ExprKind::TypeAlias(_, _, expr) => expr.visit(ctx, callback),
 
-            Expr::TypedCall(callee, _, args) => {
+            ExprKind::TypedCall(callee, _, args) => {
                 callee.visit(ctx, callback);
                 for arg in args {
                     arg.visit(ctx, callback);
                 }
             }
 
-            Expr::Directive(directive, expr) => {
+            ExprKind::Directive(directive, expr) => {
                 directive.visit(ctx, callback);
                 expr.visit(ctx, callback);
             }
@@ -16,7 +16,7 @@ auto a() {
   return 2;
 }
 
-auto b(c, d) {
+auto b(auto c, auto d) {
   2;
   return c;
 }
@@ -28,7 +28,7 @@ int add(int a, int b) {
 }
 variant<bool, int> n = 2;
 
-auto k() {
+void k() {
   if ((a > 2)) {
     true;
   }
@@ -36,6 +36,7 @@ auto k() {
     false;
   }
   fn<T>();
+  auto x = (a - b);
 }
 auto lst = vector<int>();
 auto rng = views::iota(2, 3);
@@ -52,6 +53,7 @@ public:
 class B {
 public:
   int c;
+  B(int c = {}) : c(c) {}
   auto d(int a) {
     return (a - c);
   }
@@ -88,14 +90,14 @@ using A = b;
 template<typename T> using A = T;
 template<typename P, typename Q> using B = variant<P, Q>;
 template<typename T, typename K> struct A {
-  using key = T;
-  using value = K;
+  T key;
+  K value;
 };
 template<typename Q> using P = Q::key;
 template<typename S> using R = std::enable_if_t<std::is_same<S, string>::value, S>;
 template<typename T> struct tree {
-  using value = T;
-  using children = vector<tree<T>>;
+  T value;
+  vector<tree<T>> children;
 };
 using R = optional<int>;
 using S = variant<optional<int>, optional<bool>>;
@@ -107,10 +109,10 @@ std::visit([](auto&& _) {
     print("entering");
     print("int: ", _);
   }
-  if constexpr (std::is_same_v<T, string>) {
+  else if constexpr (std::is_same_v<T, string>) {
     print("string: ", _);
   }
-  }, n);
+}, n);
 using F = std::function<optional<int>(int, float)>;
 using P = std::function<optional<int>(int, variant<bool, float>)>;
 auto _ = (a > b);
@@ -139,8 +141,7 @@ auto _ = ***a;
 auto _ = ****a;
 int* _ = &a;
 
-auto p() {
-  return ;
+void p() {
 }
 static
 inline
@@ -148,6 +149,13 @@ constexpr
 auto n = static 0;
 constexpr 
 auto k = static b;
+auto _ = ((a + b) + c);
+auto _ = ((a * b) + (c * d));
+auto _ = ((a - b) - c);
+auto _ = ((a > 1) && (b < 2));
+auto _ = (a || (b && (c == d)));
+auto _ = (((a << 2) & b) | (c ^ d));
+auto _ = (~a * *b);
 } // namespace emit
 
 int main(int argc, const char** argv) { return emit::main(argc, std::vector<std::string>(argv + 1, argv + argc)); }
@@ -0,0 +1,223 @@
+// runs the `@use` commands in the headers of the fixtures under tests/ and
+// compares what they print with the files they were redirected to, modules
+// documented with `esper run` are compiled with $CXX, clang++ or g++ and run,
+// without any of them they are run by the interpreter whose output is the
+// same, `build` is only run when it emits
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn esper(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_esper"))
+        .args(args)
+        .output()
+        .expect("failed to run esper")
+}
+
+fn fixtures(dir: &Path, found: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+
+        if path.is_dir() {
+            fixtures(&path, found);
+        } else if path.extension().is_some_and(|ext| ext == "esp") {
+            found.push(path);
+        }
+    }
+}
+
+// the commands of a fixture, paths relative to the crate root
+fn uses(source: &str) -> Vec<Vec<String>> {
+    source
+        .lines()
+        .take_while(|line| !line.trim_start().starts_with("*)"))
+        .filter_map(|line| line.split_once("@use "))
+        .map(|(_, command)| {
+            command
+                .split_whitespace()
+                .map(|word| word.strip_prefix("./").unwrap_or(word).to_string())
+                .collect()
+        })
+        .collect()
+}
+
+// the expected output of a command and whether it goes to stderr
+fn redirect(command: &[String]) -> (Vec<&str>, Option<(&str, bool)>) {
+    let args = command[1..].iter().map(String::as_str);
+
+    match command.iter().position(|word| word == ">" || word == "2>") {
+        Some(at) => (
+            args.take(at - 1).collect(),
+            Some((command[at + 1].as_str(), command[at] == "2>")),
+        ),
+        None => (args.collect(), None),
+    }
+}
+
+// the first C++ compiler that runs, $CXX before clang++ and g++
+fn cxx() -> Option<String> {
+    std::env::var("CXX")
+        .ok()
+        .into_iter()
+        .chain(["clang++".to_string(), "g++".to_string()])
+        .find(|cxx| {
+            Command::new(cxx)
+                .arg("--version")
+                .output()
+                .is_ok_and(|output| output.status.success())
+        })
+}
+
+fn run_fixture(command: &[String], cxx: Option<&str>) -> Result<(), String> {
+    let checked = ["build", "check", "interp", "run", "fmt"];
+
+    if command.first().map(String::as_str) != Some("esper")
+        || !checked.contains(&command[1].as_str())
+    {
+        return Ok(());
+    }
+
+    if command[1] == "build" {
+        return emitted(command);
+    }
+
+    let (mut args, expected) = redirect(command);
+
+    // `run -p FILE -X ...` prints what `interp FILE` does
+    if args[0] == "run" {
+        match cxx {
+            Some(cxx) => args.splice(1..1, ["--cxx", cxx]).for_each(drop),
+            None => {
+                let file = args
+                    .iter()
+                    .find(|arg| arg.ends_with(".esp"))
+                    .copied()
+                    .unwrap();
+                args = vec!["interp", file];
+            }
+        }
+    }
+
+    let output = esper(&args);
+
+    let Some((path, stderr)) = expected else {
+        return match output.status.success() {
+            true => Ok(()),
+            false => Err(format!(
+                "exited with {}\n{}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            )),
+        };
+    };
+
+    let found = String::from_utf8_lossy(match stderr {
+        true => &output.stderr,
+        false => &output.stdout,
+    })
+    .into_owned();
+    let expected = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+
+    match found == expected {
+        true => Ok(()),
+        false => Err(format!(
+            "differs from {}:\n{}{}",
+            path,
+            found,
+            String::from_utf8_lossy(&output.stderr)
+        )),
+    }
+}
+
+// `build -e ... -o FILE` writes the C++ to a temporary file compared with FILE
+fn emitted(command: &[String]) -> Result<(), String> {
+    let (mut args, _) = redirect(command);
+
+    if !args.contains(&"-e") {
+        return Ok(());
+    }
+
+    let at = args.iter().position(|arg| *arg == "-o").unwrap() + 1;
+    let expected = args[at];
+    let output_path = std::env::temp_dir().join(format!(
+        "esper-fixture-{}-{}",
+        std::process::id(),
+        expected.replace('/', "-")
+    ));
+    let output_str = output_path.to_string_lossy().into_owned();
+    args[at] = &output_str;
+
+    let output = esper(&args);
+    let found = fs::read_to_string(&output_path);
+    let _ = fs::remove_file(&output_path);
+
+    if !output.status.success() {
+        return Err(format!(
+            "exited with {}\n{}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    let found = found.map_err(|err| format!("{}: {}", output_str, err))?;
+    let expected_source =
+        fs::read_to_string(expected).map_err(|err| format!("{}: {}", expected, err))?;
+
+    match found == expected_source {
+        true => Ok(()),
+        false => Err(format!("differs from {}:\n{}", expected, found)),
+    }
+}
+
+#[test]
+fn fixtures_match_their_output() {
+    let mut paths = vec![];
+    fixtures(Path::new("tests"), &mut paths);
+    paths.sort();
+
+    let mut failures = vec![];
+    let cxx = cxx();
+
+    if cxx.is_none() {
+        eprintln!("no C++ compiler found, `esper run` fixtures are run by the interpreter");
+    }
+
+    // fixtures compiling C++ take a while, every command runs on its own thread
+    let commands = paths
+        .iter()
+        .flat_map(|path| {
+            let source = fs::read_to_string(path).unwrap();
+            uses(&source)
+                .into_iter()
+                .map(move |command| (path, command))
+        })
+        .collect::<Vec<_>>();
+
+    std::thread::scope(|scope| {
+        let runs = commands
+            .iter()
+            .map(|(path, command)| {
+                let cxx = cxx.as_deref();
+                (
+                    path,
+                    command,
+                    scope.spawn(move || run_fixture(command, cxx)),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        for (path, command, run) in runs {
+            if let Err(err) = run.join().unwrap() {
+                failures.push(format!(
+                    "{}: {}\n{}",
+                    path.display(),
+                    command.join(" "),
+                    err
+                ));
+            }
+        }
+    });
+
+    assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+}